@@ -0,0 +1,589 @@
+use crate::error::Error;
+use ab_glyph::{Font, FontArc, GlyphImageFormat, ScaleFont};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// A glyph made of pixels, drawn without antialiasing.
+#[derive(Debug, Clone)]
+pub struct BitmapGlyph {
+    width: usize,
+    height: usize,
+    /// From the pen position to the left of the bitmap.
+    x_offset: i32,
+    /// From the baseline up to the bottom of the bitmap.
+    y_offset: i32,
+    advance: i32,
+    bits: Vec<bool>,
+}
+
+impl BitmapGlyph {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn x_offset(&self) -> i32 {
+        self.x_offset
+    }
+
+    /// Get the offset from the baseline down to the top of the bitmap.
+    pub fn top(&self) -> i32 {
+        -(self.y_offset + self.height as i32)
+    }
+
+    pub fn advance(&self) -> i32 {
+        self.advance
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.bits.get(y * self.width + x).copied().unwrap_or(false)
+    }
+}
+
+/// A font of fixed size, from a BDF or PCF file or the bitmap strikes of an OpenType font.
+#[derive(Debug, Clone)]
+pub struct BitmapFont {
+    ascent: i32,
+    descent: i32,
+    glyphs: HashMap<char, BitmapGlyph>,
+}
+
+impl BitmapFont {
+    pub fn ascent(&self) -> i32 {
+        self.ascent
+    }
+
+    /// Get the distance below the baseline, as a positive number.
+    pub fn descent(&self) -> i32 {
+        self.descent
+    }
+
+    pub fn glyph(&self, ch: char) -> Option<&BitmapGlyph> {
+        self.glyphs.get(&ch)
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.glyphs.keys().copied()
+    }
+
+    /// Get this with each pixel made `factor` times larger, keeping glyphs sharp.
+    pub fn magnified(&self, factor: usize) -> Self {
+        let glyphs = self
+            .glyphs
+            .iter()
+            .map(|(ch, glyph)| {
+                let width = glyph.width * factor;
+                let bits = (0..glyph.height * factor)
+                    .flat_map(|y| (0..width).map(move |x| glyph.pixel(x / factor, y / factor)))
+                    .collect();
+                let glyph = BitmapGlyph {
+                    width,
+                    height: glyph.height * factor,
+                    x_offset: glyph.x_offset * factor as i32,
+                    y_offset: glyph.y_offset * factor as i32,
+                    advance: glyph.advance * factor as i32,
+                    bits,
+                };
+                (*ch, glyph)
+            })
+            .collect();
+        Self {
+            ascent: self.ascent * factor as i32,
+            descent: self.descent * factor as i32,
+            glyphs,
+        }
+    }
+
+    /// Whether `data` is a BDF or PCF font, possibly gzipped.
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(b"STARTFONT")
+            || data.starts_with(PCF_MAGIC)
+            || data.starts_with(GZIP_MAGIC)
+    }
+
+    /// Load a BDF or PCF font, possibly gzipped.
+    pub fn from_data(data: &[u8]) -> Result<Self, Error> {
+        if data.starts_with(GZIP_MAGIC) {
+            let mut decompressed = vec![];
+            flate2::read::GzDecoder::new(data)
+                .read_to_end(&mut decompressed)
+                .map_err(|_| Error::InvalidFont)?;
+            Self::from_data(&decompressed)
+        } else if data.starts_with(PCF_MAGIC) {
+            parse_pcf(data).ok_or(Error::InvalidFont)
+        } else {
+            std::str::from_utf8(data)
+                .ok()
+                .and_then(parse_bdf)
+                .ok_or(Error::InvalidFont)
+        }
+    }
+
+    /// Take the bitmap strike closest to `size` from an OpenType font,
+    /// or `None` if it has outlines.
+    pub fn from_strikes(font: &FontArc, size: f32) -> Option<Self> {
+        let (sample, _) = font.codepoint_ids().find(|(_, ch)| ch.is_alphanumeric())?;
+        if font.outline(sample).is_some() {
+            return None;
+        }
+        let size = size.round() as u16;
+        let pixels_per_em = font.glyph_raster_image2(sample, size)?.pixels_per_em;
+        let scaled = font.as_scaled(pixels_per_em as f32);
+        let glyphs = font
+            .codepoint_ids()
+            .filter_map(|(id, ch)| {
+                let image = font.glyph_raster_image2(id, size)?;
+                let (width, height) = (image.width as usize, image.height as usize);
+                let bits = decode_strike(&image.format, image.data, width, height)?;
+                let glyph = BitmapGlyph {
+                    width,
+                    height,
+                    x_offset: image.origin.x as i32,
+                    y_offset: image.origin.y as i32,
+                    advance: scaled.h_advance(id).round() as i32,
+                    bits,
+                };
+                Some((ch, glyph))
+            })
+            .collect();
+        Some(Self {
+            ascent: scaled.ascent().round() as i32,
+            descent: -scaled.descent().round() as i32,
+            glyphs,
+        })
+    }
+}
+
+const PCF_MAGIC: &[u8] = b"\x01fcp";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// Unpack a strike bitmap to one value per pixel.
+fn decode_strike(
+    format: &GlyphImageFormat,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Option<Vec<bool>> {
+    let (depth, padded) = match format {
+        GlyphImageFormat::BitmapMono => (1, true),
+        GlyphImageFormat::BitmapMonoPacked => (1, false),
+        GlyphImageFormat::BitmapGray2 => (2, true),
+        GlyphImageFormat::BitmapGray2Packed => (2, false),
+        GlyphImageFormat::BitmapGray4 => (4, true),
+        GlyphImageFormat::BitmapGray4Packed => (4, false),
+        GlyphImageFormat::BitmapGray8 => (8, true),
+        _ => return None,
+    };
+    let row_bits = if padded {
+        (width * depth).div_ceil(8) * 8
+    } else {
+        width * depth
+    };
+    let mut bits = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let offset = y * row_bits + x * depth;
+            let byte = *data.get(offset / 8)?;
+            let value = (byte << (offset % 8)) >> (8 - depth);
+            // Gray pixels of at least half intensity are set
+            bits.push(value >= 1 << (depth - 1));
+        }
+    }
+    Some(bits)
+}
+
+fn parse_bdf(data: &str) -> Option<BitmapFont> {
+    let mut ascent = None;
+    let mut descent = None;
+    let mut bounding_box = None;
+    let mut glyphs = HashMap::new();
+    let mut lines = data.lines();
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("FONTBOUNDINGBOX") => {
+                let numbers: Vec<i32> = words.filter_map(|v| v.parse().ok()).collect();
+                bounding_box = Some((*numbers.get(1)?, *numbers.get(3)?));
+            }
+            Some("FONT_ASCENT") => ascent = words.next()?.parse().ok(),
+            Some("FONT_DESCENT") => descent = words.next()?.parse().ok(),
+            Some("STARTCHAR") => {
+                let mut encoding = None;
+                let mut advance = 0;
+                let mut bbx = (0, 0, 0, 0);
+                let mut bits = vec![];
+                while let Some(line) = lines.next() {
+                    let mut words = line.split_whitespace();
+                    match words.next() {
+                        Some("ENCODING") => encoding = words.next()?.parse::<i64>().ok(),
+                        Some("DWIDTH") => advance = words.next()?.parse().ok()?,
+                        Some("BBX") => {
+                            let numbers: Vec<i32> = words.filter_map(|v| v.parse().ok()).collect();
+                            bbx = (
+                                *numbers.first()?,
+                                *numbers.get(1)?,
+                                *numbers.get(2)?,
+                                *numbers.get(3)?,
+                            );
+                        }
+                        Some("BITMAP") => {
+                            let width = bbx.0.max(0) as usize;
+                            for _ in 0..bbx.1 {
+                                let hex = lines.next()?.trim();
+                                let row = u128::from_str_radix(hex, 16).ok()?;
+                                let row_bits = hex.len() * 4;
+                                bits.extend(
+                                    (0..width).map(|x| {
+                                        x < row_bits && (row >> (row_bits - 1 - x)) & 1 != 0
+                                    }),
+                                );
+                            }
+                        }
+                        Some("ENDCHAR") => break,
+                        _ => {}
+                    }
+                }
+                // Glyphs outside of the encoding have -1
+                let Some(ch) = encoding
+                    .and_then(|v| u32::try_from(v).ok())
+                    .and_then(char::from_u32)
+                else {
+                    continue;
+                };
+                glyphs.insert(
+                    ch,
+                    BitmapGlyph {
+                        width: bbx.0.max(0) as usize,
+                        height: bbx.1.max(0) as usize,
+                        x_offset: bbx.2,
+                        y_offset: bbx.3,
+                        advance,
+                        bits,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+    let (height, y_offset) = bounding_box.unwrap_or_default();
+    Some(BitmapFont {
+        ascent: ascent.unwrap_or(height + y_offset),
+        descent: descent.unwrap_or(-y_offset),
+        glyphs,
+    })
+}
+
+const PCF_ACCELERATORS: u32 = 1 << 1;
+const PCF_METRICS: u32 = 1 << 2;
+const PCF_BITMAPS: u32 = 1 << 3;
+const PCF_BDF_ENCODINGS: u32 = 1 << 5;
+const PCF_BDF_ACCELERATORS: u32 = 1 << 8;
+
+const PCF_BYTE_MASK: u32 = 1 << 2;
+const PCF_BIT_MASK: u32 = 1 << 3;
+const PCF_COMPRESSED_METRICS: u32 = 1 << 8;
+
+/// A table of a PCF file, read in the byte order given by its format.
+struct PcfTable<'a> {
+    data: &'a [u8],
+    format: u32,
+    position: usize,
+}
+
+impl<'a> PcfTable<'a> {
+    fn find(file: &'a [u8], kind: u32) -> Option<Self> {
+        let count = u32::from_le_bytes(file.get(4..8)?.try_into().ok()?) as usize;
+        (0..count).find_map(|idx| {
+            let entry = file.get(8 + idx * 16..8 + idx * 16 + 16)?;
+            let field = |n: usize| u32::from_le_bytes(entry[n * 4..n * 4 + 4].try_into().unwrap());
+            if field(0) != kind {
+                return None;
+            }
+            let (size, offset) = (field(2) as usize, field(3) as usize);
+            let data = file.get(offset..offset + size)?;
+            // The format is repeated at the start of the table, always little-endian
+            let format = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+            Some(Self {
+                data,
+                format,
+                position: 4,
+            })
+        })
+    }
+
+    fn big_endian(&self) -> bool {
+        self.format & PCF_BYTE_MASK != 0
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + N)?
+            .try_into()
+            .ok()?;
+        self.position += N;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes()?;
+        Some(if self.big_endian() {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes()?;
+        Some(if self.big_endian() {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// Metrics of a PCF glyph: left and right bearings, advance, ascent and descent.
+type PcfMetrics = (i32, i32, i32, i32, i32);
+
+fn parse_pcf(file: &[u8]) -> Option<BitmapFont> {
+    let mut table = PcfTable::find(file, PCF_METRICS)?;
+    let metrics: Vec<PcfMetrics> = if table.format & PCF_COMPRESSED_METRICS != 0 {
+        let count = table.u16()?;
+        (0..count)
+            .map(|_| {
+                let mut next = || Some(table.u8()? as i32 - 0x80);
+                Some((next()?, next()?, next()?, next()?, next()?))
+            })
+            .collect::<Option<_>>()?
+    } else {
+        let count = table.u32()?;
+        (0..count)
+            .map(|_| {
+                let mut next = || Some(table.u16()? as i16 as i32);
+                let metrics = (next()?, next()?, next()?, next()?, next()?);
+                table.u16()?; // Attributes
+                Some(metrics)
+            })
+            .collect::<Option<_>>()?
+    };
+
+    let mut table = PcfTable::find(file, PCF_BITMAPS)?;
+    let count = table.u32()? as usize;
+    let offsets = (0..count)
+        .map(|_| table.u32())
+        .collect::<Option<Vec<_>>>()?;
+    let sizes = [table.u32()?, table.u32()?, table.u32()?, table.u32()?];
+    let pad = 1 << (table.format & 3);
+    let unit = 1 << ((table.format >> 4) & 3);
+    let mut bitmaps = table
+        .data
+        .get(table.position..table.position + sizes[(table.format & 3) as usize] as usize)?
+        .to_vec();
+    let msb_bits = table.format & PCF_BIT_MASK != 0;
+    if msb_bits != table.big_endian() && unit > 1 {
+        for chunk in bitmaps.chunks_exact_mut(unit) {
+            chunk.reverse();
+        }
+    }
+    if !msb_bits {
+        for byte in &mut bitmaps {
+            *byte = byte.reverse_bits();
+        }
+    }
+
+    let mut table = PcfTable::find(file, PCF_BDF_ENCODINGS)?;
+    let (min_byte2, max_byte2) = (table.u16()?, table.u16()?);
+    let (min_byte1, max_byte1) = (table.u16()?, table.u16()?);
+    table.u16()?; // Default character
+    let mut glyphs = HashMap::new();
+    for byte1 in min_byte1..=max_byte1 {
+        for byte2 in min_byte2..=max_byte2 {
+            let index = table.u16()? as usize;
+            let (Some(ch), Some(metrics), Some(offset)) = (
+                char::from_u32((byte1 as u32) << 8 | byte2 as u32),
+                metrics.get(index),
+                offsets.get(index),
+            ) else {
+                continue;
+            };
+            let (left, right, advance, ascent, descent) = *metrics;
+            let width = (right - left).max(0) as usize;
+            let height = (ascent + descent).max(0) as usize;
+            let row_bytes = width.div_ceil(8).div_ceil(pad) * pad;
+            let mut bits = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let byte = bitmaps.get(*offset as usize + y * row_bytes + x / 8)?;
+                    bits.push(byte & (0x80 >> (x % 8)) != 0);
+                }
+            }
+            glyphs.insert(
+                ch,
+                BitmapGlyph {
+                    width,
+                    height,
+                    x_offset: left,
+                    y_offset: -descent,
+                    advance,
+                    bits,
+                },
+            );
+        }
+    }
+
+    let mut table = PcfTable::find(file, PCF_BDF_ACCELERATORS)
+        .or_else(|| PcfTable::find(file, PCF_ACCELERATORS))?;
+    table.bytes::<8>()?; // Flags
+    let ascent = table.u32()? as i32;
+    let descent = table.u32()? as i32;
+    Some(BitmapFont {
+        ascent,
+        descent,
+        glyphs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const BDF: &str = "STARTFONT 2.1
+FONTBOUNDINGBOX 4 6 0 -2
+FONT_ASCENT 5
+FONT_DESCENT 2
+STARTCHAR A
+ENCODING 65
+DWIDTH 5 0
+BBX 3 2 1 -1
+BITMAP
+A0
+40
+ENDCHAR
+STARTCHAR unencoded
+ENCODING -1
+DWIDTH 5 0
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+ENDFONT
+";
+
+    /// Get the pixels of `glyph` as rows of `#` and `.`.
+    fn rows(glyph: &BitmapGlyph) -> Vec<String> {
+        (0..glyph.height())
+            .map(|y| {
+                (0..glyph.width())
+                    .map(|x| if glyph.pixel(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Build a little-endian PCF file of `tables`, each with its kind and content after the format.
+    fn pcf(tables: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = PCF_MAGIC.to_vec();
+        file.extend((tables.len() as u32).to_le_bytes());
+        let mut offset = 8 + tables.len() * 16;
+        for (kind, format, content) in tables {
+            let size = 4 + content.len();
+            for field in [*kind, *format, size as u32, offset as u32] {
+                file.extend(field.to_le_bytes());
+            }
+            offset += size;
+        }
+        for (_, format, content) in tables {
+            file.extend(format.to_le_bytes());
+            file.extend(content);
+        }
+        file
+    }
+
+    #[test]
+    fn bdf_glyphs() {
+        let font = BitmapFont::from_data(BDF.as_bytes()).unwrap();
+        assert_eq!((font.ascent(), font.descent()), (5, 2));
+        assert_eq!(font.chars().collect::<Vec<_>>(), ['A']);
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!(rows(glyph), ["#.#", ".#."]);
+        assert_eq!((glyph.x_offset(), glyph.top(), glyph.advance()), (1, -1, 5));
+    }
+
+    #[test]
+    fn bdf_metrics_default_to_the_bounding_box() {
+        let bdf = BDF.replace("FONT_ASCENT 5\nFONT_DESCENT 2\n", "");
+        let font = BitmapFont::from_data(bdf.as_bytes()).unwrap();
+        assert_eq!((font.ascent(), font.descent()), (4, 2));
+    }
+
+    #[test]
+    fn gzipped_fonts() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(BDF.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+        assert!(BitmapFont::detect(&data));
+        let font = BitmapFont::from_data(&data).unwrap();
+        assert_eq!(rows(font.glyph('A').unwrap()), ["#.#", ".#."]);
+    }
+
+    #[test]
+    fn other_files_are_not_bitmap_fonts() {
+        assert!(!BitmapFont::detect(b"\x00\x01\x00\x00"));
+        assert!(BitmapFont::from_data(PCF_MAGIC).is_err());
+    }
+
+    #[test]
+    fn magnified_glyphs_stay_sharp() {
+        let font = BitmapFont::from_data(BDF.as_bytes()).unwrap().magnified(2);
+        assert_eq!((font.ascent(), font.descent()), (10, 4));
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!(rows(glyph), ["##..##", "##..##", "..##..", "..##.."]);
+        assert_eq!(
+            (glyph.x_offset(), glyph.top(), glyph.advance()),
+            (2, -2, 10)
+        );
+    }
+
+    #[test]
+    fn pcf_glyphs() {
+        // Compressed metrics are offset by 0x80: bearings 1 and 4, advance 5, ascent 1, descent 1
+        let metrics = [1u8, 0, 0x81, 0x84, 0x85, 0x81, 0x81].to_vec();
+        // One glyph at offset 0, with rows padded to a byte
+        let mut bitmaps = 1u32.to_le_bytes().to_vec();
+        bitmaps.extend(0u32.to_le_bytes());
+        for size in [2u32, 4, 8, 16] {
+            bitmaps.extend(size.to_le_bytes());
+        }
+        bitmaps.extend([0b1010_0000, 0b0100_0000]);
+        // Only the code point 'A', as glyph 0
+        let mut encodings = vec![];
+        for value in [0x41u16, 0x41, 0, 0, 0, 0] {
+            encodings.extend(value.to_le_bytes());
+        }
+        let mut accelerators = vec![0; 8];
+        accelerators.extend(5u32.to_le_bytes());
+        accelerators.extend(2u32.to_le_bytes());
+        let data = pcf(&[
+            (PCF_METRICS, PCF_COMPRESSED_METRICS, metrics),
+            (PCF_BITMAPS, PCF_BIT_MASK, bitmaps),
+            (PCF_BDF_ENCODINGS, 0, encodings),
+            (PCF_ACCELERATORS, 0, accelerators),
+        ]);
+        assert!(BitmapFont::detect(&data));
+        let font = BitmapFont::from_data(&data).unwrap();
+        assert_eq!((font.ascent(), font.descent()), (5, 2));
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!(rows(glyph), ["#.#", ".#."]);
+        assert_eq!((glyph.x_offset(), glyph.top(), glyph.advance()), (1, -1, 5));
+    }
+}
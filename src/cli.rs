@@ -1,3 +1,4 @@
+use crate::font::FontPattern;
 use clap::Parser;
 
 #[derive(Clone, Debug, Copy, PartialEq)]
@@ -12,6 +13,10 @@ impl Color {
     pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self { r, g, b, a }
     }
+
+    pub fn alpha(&self) -> u8 {
+        self.a
+    }
 }
 
 impl Color {
@@ -25,16 +30,16 @@ impl Color {
         let rb: u32 = bg.r.into();
         let gb: u32 = bg.g.into();
         let bb: u32 = bg.b.into();
-        let ac: u8 = (255 as u32 - ((255 - aa) * (255 - ab) >> 8))
+        let ac: u8 = (255_u32 - (((255 - aa) * (255 - ab)) >> 8))
             .try_into()
             .unwrap();
-        let rc: u8 = ((ra * (aa) >> 8) + (rb * (ab) * (255 - aa) >> 16))
+        let rc: u8 = (((ra * aa) >> 8) + ((rb * ab * (255 - aa)) >> 16))
             .try_into()
             .unwrap();
-        let gc: u8 = ((ga * (aa) >> 8) + (gb * (ab) * (255 - aa) >> 16))
+        let gc: u8 = (((ga * aa) >> 8) + ((gb * ab * (255 - aa)) >> 16))
             .try_into()
             .unwrap();
-        let bc: u8 = ((ba * (aa) >> 8) + (bb * (ab) * (255 - aa) >> 16))
+        let bc: u8 = (((ba * aa) >> 8) + ((bb * ab * (255 - aa)) >> 16))
             .try_into()
             .unwrap();
         Self {
@@ -45,6 +50,17 @@ impl Color {
         }
     }
 
+    // Mix this with another color; `t` = 0 gives this and `t` = 1 gives the other.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Self {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+
     // Combine this with alpha
     pub fn with_alpha(&self, alpha: u8) -> Self {
         let orig: u32 = self.a.into();
@@ -54,11 +70,11 @@ impl Color {
     }
 }
 
-impl Into<[u8; 4]> for &Color {
+impl From<&Color> for [u8; 4] {
     /// Translate this to byte reprensation,
     /// in ARGB8888 format.
-    fn into(self) -> [u8; 4] {
-        [self.b, self.g, self.r, self.a]
+    fn from(value: &Color) -> Self {
+        [value.b, value.g, value.r, value.a]
     }
 }
 
@@ -110,7 +126,7 @@ impl core::str::FromStr for Color {
                     Color::new(
                         TryInto::<u8>::try_into((number & 0xf00) >> 8).map_err(|_| ())? * 0x11,
                         TryInto::<u8>::try_into((number & 0x0f0) >> 4).map_err(|_| ())? * 0x11,
-                        TryInto::<u8>::try_into((number & 0x00f) >> 0).map_err(|_| ())? * 0x11,
+                        TryInto::<u8>::try_into(number & 0x00f).map_err(|_| ())? * 0x11,
                         0xff,
                     )
                 }
@@ -119,7 +135,7 @@ impl core::str::FromStr for Color {
                     Color::new(
                         TryInto::<u8>::try_into((number & 0x0f00) >> 8).map_err(|_| ())? * 0x11,
                         TryInto::<u8>::try_into((number & 0x00f0) >> 4).map_err(|_| ())? * 0x11,
-                        TryInto::<u8>::try_into((number & 0x000f) >> 0).map_err(|_| ())? * 0x11,
+                        TryInto::<u8>::try_into(number & 0x000f).map_err(|_| ())? * 0x11,
                         TryInto::<u8>::try_into((number & 0xf000) >> 12).map_err(|_| ())? * 0x11,
                     )
                 }
@@ -148,6 +164,139 @@ impl core::str::FromStr for Color {
     }
 }
 
+/// A linear gradient, in the form of `<angle>:<color>[@<position>],...`.
+///
+/// The angle is in degrees, clockwise from left-to-right.
+/// Stops without a position are spread evenly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+    angle: f32,
+    stops: Vec<(f32, Color)>,
+}
+
+impl Gradient {
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+
+    /// Get the color at `t`, between 0 and 1 along the gradient.
+    pub fn color_at(&self, t: f32) -> Color {
+        let next = self.stops.partition_point(|(position, _)| *position <= t);
+        match (self.stops.get(next.wrapping_sub(1)), self.stops.get(next)) {
+            (Some((from, from_color)), Some((to, to_color))) => {
+                from_color.lerp(to_color, (t - from) / (to - from))
+            }
+            (Some((_, color)), None) | (None, Some((_, color))) => *color,
+            (None, None) => Color::new(0, 0, 0, 0),
+        }
+    }
+}
+
+impl core::str::FromStr for Gradient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (angle, stops) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Missing angle: {}", s))?;
+        let angle = angle
+            .parse()
+            .map_err(|_| format!("Invalid angle: {}", angle))?;
+        let stops = stops
+            .split(',')
+            .map(|stop| match stop.split_once('@') {
+                Some((color, position)) => Ok((
+                    Some(
+                        position
+                            .parse::<f32>()
+                            .map_err(|_| format!("Invalid position: {}", position))?,
+                    ),
+                    color.parse()?,
+                )),
+                None => Ok((None, stop.parse()?)),
+            })
+            .collect::<Result<Vec<(Option<f32>, Color)>, String>>()?;
+        if stops.len() < 2 {
+            return Err(format!("Gradient needs at least 2 colors: {}", s));
+        }
+        let last = (stops.len() - 1) as f32;
+        let mut stops: Vec<_> = stops
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (position, color))| {
+                (position.unwrap_or(idx as f32 / last).clamp(0., 1.), color)
+            })
+            .collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self { angle, stops })
+    }
+}
+
+/// The size and position of the bar, in the form of `[<width>]x[<height>][+<x>[+<y>]]`.
+///
+/// A missing width stretches the bar over the output,
+/// and a missing height is derived from the fonts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Geometry {
+    width: Option<u32>,
+    height: Option<u32>,
+    x: i32,
+    y: i32,
+}
+
+impl Geometry {
+    pub fn width(&self) -> Option<u32> {
+        self.width
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        self.height
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+}
+
+impl core::str::FromStr for Geometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (size, offsets) = s.split_once('+').unwrap_or((s, ""));
+        let (width, height) = size
+            .split_once('x')
+            .ok_or_else(|| format!("Missing size: {}", s))?;
+        let dimension = |v: &str| {
+            (!v.is_empty())
+                .then(|| v.parse().map_err(|_| format!("Invalid size: {}", v)))
+                .transpose()
+        };
+        let mut offsets = offsets
+            .split('+')
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse().map_err(|_| format!("Invalid position: {}", v)));
+        Ok(Self {
+            width: dimension(width)?,
+            height: dimension(height)?,
+            x: offsets.next().transpose()?.unwrap_or(0),
+            y: offsets.next().transpose()?.unwrap_or(0),
+        })
+    }
+}
+
+/// How a background image covers the bar.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ImageMode {
+    /// Stretch the image to the bar size
+    Scale,
+    /// Repeat the image at its own size
+    Tile,
+}
+
 #[derive(Parser)]
 pub struct Config {
     #[arg(value_parser=|v:&str| v.parse::<Color>(), default_value="#ffffff", short='B')]
@@ -158,8 +307,35 @@ pub struct Config {
     permaent: bool,
     #[arg(short)]
     name: Option<String>,
-    #[arg(short, long = "font", default_value = "sans-serif")]
-    fonts: Vec<String>,
+    /// A font pattern, e.g. `Iosevka:size=11:weight=bold:style=italic`; repeat for fallbacks
+    #[arg(short, long = "font", default_value = "sans-serif", value_parser=|v:&str| v.parse::<FontPattern>())]
+    fonts: Vec<FontPattern>,
+    /// Search the system fonts for characters missing from the given ones
+    #[arg(long)]
+    font_fallback: bool,
+    /// The bar geometry, e.g. `x24` or `1200x30+100+10`
+    #[arg(short, long, value_parser=|v:&str| v.parse::<Geometry>())]
+    geometry: Option<Geometry>,
+    /// The space above and below the text, when the height isn't given
+    #[arg(long, default_value_t = 4)]
+    padding: u32,
+    /// Paint a linear gradient over the background color, e.g. `90:#222,#444@0.3,#222`
+    #[arg(long, value_parser=|v:&str| v.parse::<Gradient>())]
+    gradient: Option<Gradient>,
+    /// Paint an image (PNG or SVG) over the background
+    #[arg(long)]
+    background_image: Option<std::path::PathBuf>,
+    #[arg(long, value_enum, default_value_t = ImageMode::Scale)]
+    background_image_mode: ImageMode,
+    /// How fast scrolling segments move, in pixels per second
+    #[arg(long, default_value_t = 30.)]
+    marquee_speed: f32,
+    /// How long scrolling segments stay still at each end, in seconds
+    #[arg(long, default_value_t = 1.5)]
+    marquee_pause: f32,
+    /// How long the pointer rests on an area before its tooltip shows, in seconds
+    #[arg(long, default_value_t = 0.5)]
+    tooltip_delay: f32,
 }
 
 impl Config {
@@ -179,7 +355,43 @@ impl Config {
         self.name.as_ref()
     }
 
-    pub fn fonts(&self) -> &[String] {
+    pub fn fonts(&self) -> &[FontPattern] {
         &self.fonts
     }
+
+    pub fn font_fallback(&self) -> bool {
+        self.font_fallback
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry.clone().unwrap_or_default()
+    }
+
+    pub fn padding(&self) -> u32 {
+        self.padding
+    }
+
+    pub fn gradient(&self) -> Option<&Gradient> {
+        self.gradient.as_ref()
+    }
+
+    pub fn background_image(&self) -> Option<&std::path::Path> {
+        self.background_image.as_deref()
+    }
+
+    pub fn background_image_mode(&self) -> ImageMode {
+        self.background_image_mode
+    }
+
+    pub fn marquee_speed(&self) -> f32 {
+        self.marquee_speed
+    }
+
+    pub fn marquee_pause(&self) -> f32 {
+        self.marquee_pause
+    }
+
+    pub fn tooltip_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f32(self.tooltip_delay.max(0.))
+    }
 }
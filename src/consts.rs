@@ -1,12 +1,39 @@
 const LEFT_MOUSE: u32 = 272;
 const RIGHT_MOUSE: u32 = 273;
 const MIDDLE_MOUSE: u32 = 274;
+/// Usually the back button.
+const SIDE_MOUSE: u32 = 275;
+/// Usually the forward button.
+const EXTRA_MOUSE: u32 = 276;
+
+/// How long a touch is held to act as button 3.
+pub const LONG_PRESS: std::time::Duration = std::time::Duration::from_millis(500);
+/// How far a touch moves sideways, in logical pixels, to act as the scroll buttons.
+pub const SWIPE_DISTANCE: f64 = 30.;
 
 pub fn wayland2bar(button: u32) -> Option<u32> {
     Some(match button {
         LEFT_MOUSE => 1,
         MIDDLE_MOUSE => 3,
         RIGHT_MOUSE => 2,
+        SIDE_MOUSE => 8,
+        EXTRA_MOUSE => 9,
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_keep_their_markup_numbers() {
+        assert_eq!(wayland2bar(LEFT_MOUSE), Some(1));
+        assert_eq!(wayland2bar(RIGHT_MOUSE), Some(2));
+        assert_eq!(wayland2bar(MIDDLE_MOUSE), Some(3));
+        assert_eq!(wayland2bar(SIDE_MOUSE), Some(8));
+        assert_eq!(wayland2bar(EXTRA_MOUSE), Some(9));
+        // BTN_FORWARD
+        assert_eq!(wayland2bar(277), None);
+    }
+}
@@ -4,6 +4,8 @@ use thiserror::Error;
 pub enum Error {
     PointOutbound,
     FontNotFound,
+    InvalidFont,
+    InvalidImage,
     Unknown(#[from] Box<dyn std::error::Error>),
 }
 
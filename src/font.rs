@@ -0,0 +1,620 @@
+use crate::bitmap::{BitmapFont, BitmapGlyph};
+use crate::error::Error;
+use ab_glyph::{Font, FontArc, FontVec, GlyphId, PxScaleFont, ScaleFont, VariableFont};
+use font_kit::family_name::FamilyName;
+use font_kit::handle::Handle;
+use font_kit::properties::{Properties, Style, Weight};
+use font_kit::source::SystemSource;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::thread::JoinHandle;
+
+/// The size of the first font, when it isn't given, in pixels.
+const DEFAULT_SIZE: f32 = 20.;
+/// Used to convert point sizes to pixels, like Xft does without a configured DPI.
+const DPI: f32 = 96.;
+
+/// A font selected by a fontconfig-style pattern, e.g. `Iosevka:size=11:weight=bold`.
+///
+/// Sizes are either in points (`size`) or in pixels (`pixelsize`).
+/// OpenType features are enabled with `fontfeatures=tnum,zero,ss01`,
+/// and variable fonts are set up with `fontvariations=wght=600,wdth=80`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontPattern {
+    family: String,
+    /// In pixels.
+    size: Option<f32>,
+    properties: Properties,
+    settings: FontSettings,
+}
+
+/// An OpenType feature setting, e.g. `ss01`, `-liga` or `salt=2`.
+#[derive(Clone, Debug, PartialEq)]
+struct Feature {
+    tag: [u8; 4],
+    /// 0 disables the feature, and other values select an alternate glyph.
+    value: u16,
+}
+
+/// The value of a variable font axis, e.g. `wght=600`.
+#[derive(Clone, Debug, PartialEq)]
+struct Variation {
+    tag: [u8; 4],
+    value: f32,
+}
+
+/// Per-font settings applied when loading it.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FontSettings {
+    features: Vec<Feature>,
+    variations: Vec<Variation>,
+}
+
+fn parse_tag(s: &str) -> Result<[u8; 4], String> {
+    <[u8; 4]>::try_from(format!("{:<4}", s).as_bytes()).map_err(|_| format!("Invalid tag: {}", s))
+}
+
+impl core::str::FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag, value) = match s.split_once('=') {
+            Some((tag, value)) => (
+                tag,
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid feature value: {}", value))?,
+            ),
+            None => match s.strip_prefix('-') {
+                Some(tag) => (tag, 0),
+                None => (s.strip_prefix('+').unwrap_or(s), 1),
+            },
+        };
+        Ok(Self {
+            tag: parse_tag(tag)?,
+            value,
+        })
+    }
+}
+
+impl core::str::FromStr for Variation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid variation: {}", s))?;
+        Ok(Self {
+            tag: parse_tag(tag)?,
+            value: value
+                .parse()
+                .map_err(|_| format!("Invalid variation value: {}", value))?,
+        })
+    }
+}
+
+impl FontPattern {
+    /// Get the size in pixels, if given.
+    pub fn size(&self) -> Option<f32> {
+        self.size
+    }
+
+    /// Find the best matching font on the system, and load it.
+    ///
+    /// A family containing a `/` is taken as the path of a font file.
+    pub fn load(&self, source: &SystemSource) -> Result<Face, Error> {
+        let handle = if self.family.contains('/') {
+            Handle::from_path(self.family.clone().into(), 0)
+        } else {
+            source
+                .select_best_match(&[FamilyName::Title(self.family.clone())], &self.properties)
+                .map_err(|_| Error::FontNotFound)?
+        };
+        load_handle(&handle, self.size.unwrap_or(DEFAULT_SIZE), &self.settings)
+    }
+}
+
+/// A scalable font, together with the glyph substitutions of its enabled features.
+#[derive(Debug, Clone)]
+pub struct OutlineFace {
+    font: PxScaleFont<FontArc>,
+    substitutions: Rc<HashMap<GlyphId, GlyphId>>,
+}
+
+impl OutlineFace {
+    pub fn font(&self) -> &PxScaleFont<FontArc> {
+        &self.font
+    }
+
+    /// Get the glyph drawn for `ch`, after feature substitutions.
+    pub fn glyph_id(&self, ch: char) -> GlyphId {
+        let id = self.font.glyph_id(ch);
+        self.substitutions.get(&id).copied().unwrap_or(id)
+    }
+
+    /// Scale this so that its x-height matches `base_x_height` at `size`.
+    fn normalized(self, size: f32, base_x_height: Option<f32>) -> Self {
+        Self {
+            font: normalized(self.font.font, size, base_x_height),
+            ..self
+        }
+    }
+}
+
+/// A loaded font, either scalable or made of pixels.
+#[derive(Debug, Clone)]
+pub enum Face {
+    Outline(OutlineFace),
+    Bitmap(Rc<BitmapFont>),
+}
+
+impl Face {
+    pub fn has_glyph(&self, ch: char) -> bool {
+        match self {
+            Face::Outline(face) => face.font.glyph_id(ch).0 != 0,
+            Face::Bitmap(font) => font.glyph(ch).is_some(),
+        }
+    }
+
+    pub fn advance(&self, ch: char) -> f32 {
+        match self {
+            Face::Outline(face) => face.font.h_advance(face.glyph_id(ch)),
+            Face::Bitmap(font) => font.glyph(ch).map_or(0, BitmapGlyph::advance) as f32,
+        }
+    }
+
+    pub fn ascent(&self) -> f32 {
+        match self {
+            Face::Outline(face) => face.font.ascent(),
+            Face::Bitmap(font) => font.ascent() as f32,
+        }
+    }
+
+    /// Get the descent, negative below the baseline.
+    pub fn descent(&self) -> f32 {
+        match self {
+            Face::Outline(face) => face.font.descent(),
+            Face::Bitmap(font) => -font.descent() as f32,
+        }
+    }
+
+    /// Get the size in pixels.
+    fn size(&self) -> f32 {
+        match self {
+            Face::Outline(face) => face.font.scale().y,
+            Face::Bitmap(font) => (font.ascent() + font.descent()) as f32,
+        }
+    }
+
+    /// Get this scaled by `factor`, to render at device pixels.
+    ///
+    /// Bitmap fonts only grow by whole pixels.
+    pub fn scaled(&self, factor: f32) -> Face {
+        match self {
+            Face::Outline(face) => Face::Outline(OutlineFace {
+                font: face
+                    .font
+                    .font
+                    .clone()
+                    .into_scaled(face.font.scale.y * factor),
+                substitutions: face.substitutions.clone(),
+            }),
+            Face::Bitmap(font) => match factor.round().max(1.) as usize {
+                1 => Face::Bitmap(font.clone()),
+                factor => Face::Bitmap(Rc::new(font.magnified(factor))),
+            },
+        }
+    }
+
+    fn x_height(&self) -> Option<f32> {
+        match self {
+            Face::Outline(face) => x_height(&face.font),
+            Face::Bitmap(font) => font.glyph('x').map(|v| v.height() as f32),
+        }
+    }
+}
+
+/// Load a font file, with outline fonts scaled to `size`.
+///
+/// Bitmap fonts keep their own size, except for OpenType ones taking their closest strike.
+fn load_handle(handle: &Handle, size: f32, settings: &FontSettings) -> Result<Face, Error> {
+    let (data, font_index) = match handle {
+        Handle::Path { path, font_index } => (
+            std::fs::read(path).map_err(|e| Error::Unknown(e.into()))?,
+            *font_index,
+        ),
+        Handle::Memory { bytes, font_index } => (bytes.to_vec(), *font_index),
+    };
+    if BitmapFont::detect(&data) {
+        return Ok(Face::Bitmap(Rc::new(BitmapFont::from_data(&data)?)));
+    }
+    let substitutions = substitutions(&data, font_index, &settings.features);
+    let mut font =
+        FontVec::try_from_vec_and_index(data, font_index).map_err(|_| Error::InvalidFont)?;
+    for variation in &settings.variations {
+        if !font.set_variation(&variation.tag, variation.value) {
+            log::warn!(
+                "Font has no {} axis",
+                String::from_utf8_lossy(&variation.tag)
+            );
+        }
+    }
+    let font = FontArc::from(font);
+    Ok(match BitmapFont::from_strikes(&font, size) {
+        Some(bitmap) => Face::Bitmap(Rc::new(bitmap)),
+        None => Face::Outline(OutlineFace {
+            font: font.into_scaled(size),
+            substitutions: Rc::new(substitutions),
+        }),
+    })
+}
+
+/// Map glyphs to their substitutes under `features`.
+///
+/// Only single and alternate substitutions are applied, since characters are drawn one by one.
+fn substitutions(data: &[u8], index: u32, features: &[Feature]) -> HashMap<GlyphId, GlyphId> {
+    use ttf_parser::gsub::{SingleSubstitution, SubstitutionSubtable};
+    let mut retval = HashMap::new();
+    if features.is_empty() {
+        return retval;
+    }
+    let Ok(face) = ttf_parser::Face::parse(data, index) else {
+        return retval;
+    };
+    let Some(gsub) = face.tables().gsub else {
+        log::warn!("Font has no features");
+        return retval;
+    };
+    // Lookups are applied in the order of the font, whatever the order of the features
+    let mut lookups = BTreeMap::new();
+    for feature in features {
+        let tag = ttf_parser::Tag::from_bytes(&feature.tag);
+        let mut found = false;
+        for record in gsub.features.into_iter().filter(|v| v.tag == tag) {
+            found = true;
+            for lookup in record.lookup_indices {
+                lookups.insert(lookup, feature.value);
+            }
+        }
+        if !found {
+            log::warn!("Font has no {} feature", tag);
+        }
+    }
+    lookups.retain(|_, value| *value != 0);
+    for id in 0..face.number_of_glyphs() {
+        let mut glyph = ttf_parser::GlyphId(id);
+        for (&index, &value) in &lookups {
+            let Some(lookup) = gsub.lookups.get(index) else {
+                continue;
+            };
+            let substitute = lookup
+                .subtables
+                .into_iter::<SubstitutionSubtable>()
+                .find_map(|subtable| match subtable {
+                    SubstitutionSubtable::Single(SingleSubstitution::Format1 {
+                        coverage,
+                        delta,
+                    }) => coverage
+                        .get(glyph)
+                        .map(|_| ttf_parser::GlyphId(glyph.0.wrapping_add_signed(delta))),
+                    SubstitutionSubtable::Single(SingleSubstitution::Format2 {
+                        coverage,
+                        substitutes,
+                    }) => coverage.get(glyph).and_then(|i| substitutes.get(i)),
+                    SubstitutionSubtable::Alternate(subtable) => subtable
+                        .coverage
+                        .get(glyph)
+                        .and_then(|i| subtable.alternate_sets.get(i))
+                        .and_then(|set| set.alternates.get(value - 1)),
+                    _ => None,
+                });
+            if let Some(substitute) = substitute {
+                glyph = substitute;
+            }
+        }
+        if glyph.0 != id {
+            retval.insert(GlyphId(id), GlyphId(glyph.0));
+        }
+    }
+    retval
+}
+
+fn parse_weight(s: &str) -> Result<Weight, String> {
+    Ok(match s.to_ascii_lowercase().as_str() {
+        "thin" => Weight::THIN,
+        "extralight" | "ultralight" => Weight::EXTRA_LIGHT,
+        "light" => Weight::LIGHT,
+        "regular" | "normal" | "book" => Weight::NORMAL,
+        "medium" => Weight::MEDIUM,
+        "semibold" | "demibold" => Weight::SEMIBOLD,
+        "bold" => Weight::BOLD,
+        "extrabold" | "ultrabold" => Weight::EXTRA_BOLD,
+        "black" | "heavy" => Weight::BLACK,
+        _ => Weight(s.parse().map_err(|_| format!("Invalid weight: {}", s))?),
+    })
+}
+
+impl core::str::FromStr for FontPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(':');
+        let family = fields.next().unwrap_or_default();
+        let mut retval = Self {
+            family: if family.is_empty() {
+                "sans-serif".into()
+            } else {
+                family.into()
+            },
+            size: None,
+            properties: Properties::new(),
+            settings: FontSettings::default(),
+        };
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid font property: {}", field))?;
+            let size = || {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| format!("Invalid size: {}", value))
+            };
+            match key {
+                "size" => retval.size = Some(size()? * DPI / 72.),
+                "pixelsize" => retval.size = Some(size()?),
+                "weight" => retval.properties.weight = parse_weight(value)?,
+                "fontfeatures" => {
+                    for feature in value.split(',') {
+                        retval.settings.features.push(feature.trim().parse()?);
+                    }
+                }
+                "fontvariations" => {
+                    for variation in value.split(',') {
+                        retval.settings.variations.push(variation.trim().parse()?);
+                    }
+                }
+                "style" | "slant" => {
+                    retval.properties.style = match value.to_ascii_lowercase().as_str() {
+                        "italic" => Style::Italic,
+                        "oblique" => Style::Oblique,
+                        "roman" | "normal" | "regular" => Style::Normal,
+                        _ => return Err(format!("Invalid style: {}", value)),
+                    }
+                }
+                _ => log::warn!("Ignoring font property {}", key),
+            }
+        }
+        Ok(retval)
+    }
+}
+
+/// Get the height of lowercase letters, or `None` if the font has no 'x'.
+fn x_height<F>(font: &PxScaleFont<F>) -> Option<f32>
+where
+    F: Font,
+{
+    font.outline_glyph(font.scaled_glyph('x'))
+        .map(|v| v.px_bounds().height())
+}
+
+/// Scale `font` so that its x-height matches `base_x_height` at `size`.
+fn normalized(font: FontArc, size: f32, base_x_height: Option<f32>) -> PxScaleFont<FontArc> {
+    let x_height_ratio = base_x_height
+        .zip(x_height(&font.as_scaled(size)))
+        .map_or(1., |(base, v)| base / v);
+    font.into_scaled(size * x_height_ratio)
+}
+
+/// Load the fonts of `patterns`.
+///
+/// Outline fonts without a size are scaled so that their x-height matches the one of the first font.
+pub fn load_fonts(patterns: &[FontPattern]) -> Result<Vec<Face>, Error> {
+    let source = SystemSource::new();
+    let faces = patterns
+        .iter()
+        .map(|v| v.load(&source))
+        .collect::<Result<Vec<_>, _>>()?;
+    let primary_size = faces.first().map_or(DEFAULT_SIZE, Face::size);
+    let base_x_height = faces.first().and_then(Face::x_height);
+    Ok(faces
+        .into_iter()
+        .zip(patterns)
+        .map(|(face, pattern)| match face {
+            Face::Outline(face) if pattern.size().is_none() => {
+                Face::Outline(face.normalized(primary_size, base_x_height))
+            }
+            face => face,
+        })
+        .collect())
+}
+
+/// Fonts of the system, searched for characters that no configured font has.
+pub struct Fallback {
+    /// Builds the index, since it reads every font of the system.
+    indexing: Option<JoinHandle<Vec<Coverage>>>,
+    /// The fonts not used yet, ordered by path so that the same font is picked each time.
+    index: Vec<Coverage>,
+    /// Characters no font has, which aren't searched again.
+    missing: HashSet<char>,
+    size: f32,
+    x_height: Option<f32>,
+}
+
+impl Fallback {
+    /// Create a fallback whose fonts are normalized to the first of `fonts`.
+    ///
+    /// The system fonts are indexed on another thread, which calls `on_ready` when done.
+    pub fn new(fonts: &[Face], on_ready: impl FnOnce() + Send + 'static) -> Self {
+        let primary = fonts.first();
+        let indexing = std::thread::spawn(move || {
+            let index = index_system_fonts();
+            on_ready();
+            index
+        });
+        Self {
+            indexing: Some(indexing),
+            index: vec![],
+            missing: HashSet::new(),
+            size: primary.map_or(DEFAULT_SIZE, Face::size),
+            x_height: primary.and_then(Face::x_height),
+        }
+    }
+
+    /// Take the index once it's built, returning whether it just became available.
+    pub fn poll(&mut self) -> bool {
+        if !self.indexing.as_ref().is_some_and(JoinHandle::is_finished) {
+            return false;
+        }
+        if let Some(indexing) = self.indexing.take() {
+            self.index = indexing.join().unwrap_or_default();
+        }
+        true
+    }
+
+    /// Append fonts to `fonts`, so that all characters of `string` are covered.
+    ///
+    /// Does nothing until the index is built.
+    pub fn cover(&mut self, fonts: &mut Vec<Face>, string: &str) {
+        if self.indexing.is_some() {
+            return;
+        }
+        for ch in string.chars() {
+            if ch.is_control()
+                || self.missing.contains(&ch)
+                || fonts.iter().any(|v| v.has_glyph(ch))
+            {
+                continue;
+            }
+            match self.find(ch) {
+                Some(font) => fonts.push(font),
+                None => {
+                    log::warn!("No font has {:?}", ch);
+                    self.missing.insert(ch);
+                }
+            }
+        }
+    }
+
+    fn find(&mut self, ch: char) -> Option<Face> {
+        while let Some(idx) = self.index.iter().position(|v| v.contains(ch)) {
+            let coverage = self.index.remove(idx);
+            let font = load_handle(&coverage.handle, self.size, &FontSettings::default());
+            let Some(font) = font.ok().filter(|v| v.has_glyph(ch)) else {
+                continue;
+            };
+            #[cfg(feature = "logs")]
+            log::info!("Falling back to {:?} for {:?}", coverage.handle, ch);
+            return Some(match font {
+                Face::Outline(face) => Face::Outline(face.normalized(self.size, self.x_height)),
+                face => face,
+            });
+        }
+        None
+    }
+}
+
+/// A font of the system and the characters it has.
+struct Coverage {
+    handle: Handle,
+    /// Sorted inclusive ranges of code points.
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Coverage {
+    fn new(handle: Handle) -> Option<Self> {
+        let Handle::Path { path, font_index } = &handle else {
+            return None;
+        };
+        let data = std::fs::read(path).ok()?;
+        let chars = charset(&data, *font_index)?;
+        Some(Self {
+            ranges: ranges(chars),
+            handle,
+        })
+    }
+
+    fn contains(&self, ch: char) -> bool {
+        let ch = ch as u32;
+        let idx = self.ranges.partition_point(|&(_, end)| end < ch);
+        self.ranges.get(idx).is_some_and(|&(start, _)| start <= ch)
+    }
+}
+
+/// Merge code points into sorted inclusive ranges.
+fn ranges(mut chars: Vec<u32>) -> Vec<(u32, u32)> {
+    chars.sort_unstable();
+    chars.dedup();
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for ch in chars {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == ch => *end = ch,
+            _ => ranges.push((ch, ch)),
+        }
+    }
+    ranges
+}
+
+/// Get the code points of a font file, from its cmap or its bitmap glyphs.
+fn charset(data: &[u8], font_index: u32) -> Option<Vec<u32>> {
+    if BitmapFont::detect(data) {
+        let font = BitmapFont::from_data(data).ok()?;
+        return Some(font.chars().map(u32::from).collect());
+    }
+    let face = ttf_parser::Face::parse(data, font_index).ok()?;
+    let mut chars = vec![];
+    for subtable in face.tables().cmap?.subtables {
+        if subtable.is_unicode() {
+            subtable.codepoints(|v| chars.push(v));
+        }
+    }
+    Some(chars)
+}
+
+/// List the fonts of the system with their characters, in a stable order.
+fn index_system_fonts() -> Vec<Coverage> {
+    let mut handles = SystemSource::new().all_fonts().unwrap_or_default();
+    handles.sort_by_key(|v| match v {
+        Handle::Path { path, font_index } => Some((path.clone(), *font_index)),
+        Handle::Memory { .. } => None,
+    });
+    handles.into_iter().filter_map(Coverage::new).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_merge_consecutive_code_points() {
+        assert_eq!(
+            ranges(vec![5, 1, 2, 3, 3, 9, 10]),
+            [(1, 3), (5, 5), (9, 10)]
+        );
+        assert_eq!(ranges(vec![]), []);
+    }
+
+    #[test]
+    fn coverage_finds_code_points_in_ranges() {
+        let coverage = Coverage {
+            handle: Handle::from_path("/dev/null".into(), 0),
+            ranges: vec![('a' as u32, 'c' as u32), ('x' as u32, 'x' as u32)],
+        };
+        assert!(coverage.contains('a'));
+        assert!(coverage.contains('c'));
+        assert!(coverage.contains('x'));
+        assert!(!coverage.contains('d'));
+        assert!(!coverage.contains('A'));
+        assert!(!coverage.contains('z'));
+    }
+
+    #[test]
+    fn charset_of_bitmap_font() {
+        let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 1 1 0 0\n\
+            STARTCHAR a\nENCODING 97\nBBX 1 1 0 0\nBITMAP\n80\nENDCHAR\n\
+            STARTCHAR b\nENCODING 98\nBBX 1 1 0 0\nBITMAP\n80\nENDCHAR\nENDFONT\n";
+        let mut chars = charset(bdf.as_bytes(), 0).unwrap();
+        chars.sort_unstable();
+        assert_eq!(chars, ['a' as u32, 'b' as u32]);
+        assert_eq!(charset(b"not a font", 0), None);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::cli::Color;
+use crate::error::Error;
+use crate::paint::{Paintable, Pixmap};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// A decoded image, before being fitted to the bar.
+enum Source {
+    Raster(Pixmap),
+    #[cfg(feature = "svg")]
+    Svg(Box<resvg::usvg::Tree>),
+}
+
+impl Source {
+    fn load(path: &Path) -> Result<Self, Error> {
+        match path.extension().and_then(|v| v.to_str()) {
+            #[cfg(feature = "png")]
+            Some("png") => Ok(Self::Raster(load_png(path)?)),
+            #[cfg(feature = "svg")]
+            Some("svg") => {
+                let data = std::fs::read(path).map_err(|e| Error::Unknown(e.into()))?;
+                let tree = resvg::usvg::Tree::from_data(&data, &Default::default())
+                    .map_err(|_| Error::InvalidImage)?;
+                Ok(Self::Svg(Box::new(tree)))
+            }
+            _ => Err(Error::InvalidImage),
+        }
+    }
+
+    /// Get the natural size of this.
+    fn size(&self) -> (usize, usize) {
+        match self {
+            Self::Raster(pixmap) => (pixmap.width(), pixmap.height()),
+            #[cfg(feature = "svg")]
+            Self::Svg(tree) => {
+                let size = tree.size().to_int_size();
+                (size.width() as usize, size.height() as usize)
+            }
+        }
+    }
+
+    /// Render this with the given height, keeping the aspect ratio.
+    fn render(&self, height: usize) -> Pixmap {
+        let (natural_width, natural_height) = self.size();
+        let width = (natural_width * height).div_ceil(natural_height.max(1));
+        self.render_to(width, height)
+    }
+
+    /// Render this stretched to the given size.
+    fn render_to(&self, width: usize, height: usize) -> Pixmap {
+        match self {
+            Self::Raster(pixmap) => pixmap.scaled(width, height),
+            #[cfg(feature = "svg")]
+            Self::Svg(tree) => {
+                let size = tree.size();
+                let Some(mut target) = resvg::tiny_skia::Pixmap::new(width as u32, height as u32)
+                else {
+                    return Pixmap::new(0, 0);
+                };
+                resvg::render(
+                    tree,
+                    resvg::tiny_skia::Transform::from_scale(
+                        width as f32 / size.width(),
+                        height as f32 / size.height(),
+                    ),
+                    &mut target.as_mut(),
+                );
+                let pixels = target
+                    .pixels()
+                    .iter()
+                    .map(|v| {
+                        let v = v.demultiply();
+                        Color::new(v.red(), v.green(), v.blue(), v.alpha())
+                    })
+                    .collect();
+                Pixmap::from_pixels(width, height, pixels).unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+fn load_png(path: &Path) -> Result<Pixmap, Error> {
+    let file = std::fs::File::open(path).map_err(|e| Error::Unknown(e.into()))?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|_| Error::InvalidImage)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|_| Error::InvalidImage)?;
+    let bytes = &buffer[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => bytes
+            .chunks_exact(4)
+            .map(|v| Color::new(v[0], v[1], v[2], v[3]))
+            .collect(),
+        png::ColorType::Rgb => bytes
+            .chunks_exact(3)
+            .map(|v| Color::new(v[0], v[1], v[2], 0xff))
+            .collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks_exact(2)
+            .map(|v| Color::new(v[0], v[0], v[0], v[1]))
+            .collect(),
+        png::ColorType::Grayscale => bytes
+            .iter()
+            .map(|v| Color::new(*v, *v, *v, 0xff))
+            .collect(),
+        png::ColorType::Indexed => return Err(Error::InvalidImage), // Expanded by the decoder
+    };
+    Pixmap::from_pixels(info.width as usize, info.height as usize, pixels)
+}
+
+/// Load the image at `path`, stretched to `size`, or at its natural size if `size` is `None`.
+pub fn load_image(path: &Path, size: Option<(usize, usize)>) -> Result<Pixmap, Error> {
+    let source = Source::load(path)?;
+    let (width, height) = size.unwrap_or_else(|| source.size());
+    Ok(source.render_to(width, height))
+}
+
+struct Entry {
+    modified: SystemTime,
+    source: Source,
+    /// The last rendering, together with the height it was rendered with.
+    rendered: Option<(usize, Rc<Pixmap>)>,
+}
+
+/// Images used in the bar, keyed by their path.
+///
+/// An image is reloaded when its modification time changes.
+#[derive(Default)]
+pub struct IconCache {
+    entries: RefCell<HashMap<PathBuf, Entry>>,
+}
+
+impl IconCache {
+    /// Get the image at `path`, scaled to `height`.
+    pub fn get(&self, path: &Path, height: usize) -> Result<Rc<Pixmap>, Error> {
+        let modified = std::fs::metadata(path)
+            .and_then(|v| v.modified())
+            .map_err(|e| Error::Unknown(e.into()))?;
+        let mut entries = self.entries.borrow_mut();
+        if entries.get(path).is_none_or(|v| v.modified != modified) {
+            #[cfg(feature = "logs")]
+            log::info!("Loading image {}", path.display());
+            let entry = Entry {
+                modified,
+                source: Source::load(path)?,
+                rendered: None,
+            };
+            entries.insert(path.to_owned(), entry);
+        }
+        let entry = entries.get_mut(path).unwrap();
+        match &entry.rendered {
+            Some((rendered_height, pixmap)) if *rendered_height == height => Ok(pixmap.clone()),
+            _ => {
+                let pixmap = Rc::new(entry.source.render(height));
+                entry.rendered = Some((height, pixmap.clone()));
+                Ok(pixmap)
+            }
+        }
+    }
+}
@@ -0,0 +1,1608 @@
+use crate::cli::Color;
+use crate::error::Error;
+use crate::font::Face;
+use crate::icon::IconCache;
+use crate::paint::{Paint, Paintable, Pixmap, Text};
+use crate::parse::{
+    Align, Attribute, AttributeAction, FieldWidth, Modifiers, StyledStringPart, Trigger,
+};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The space between the bar edges and the left and right groups.
+const EDGE_PADDING: usize = 5;
+const ELLIPSIS: char = '…';
+
+/// What the layout needs to know about the bar.
+///
+/// Sizes are in device pixels, while lengths in the markup are in logical ones.
+pub struct Context<'a> {
+    /// Scaled to device pixels.
+    pub fonts: &'a [Face],
+    pub icons: &'a IconCache,
+    pub series: &'a HashMap<String, VecDeque<f32>>,
+    pub foreground_color: Color,
+    pub background_color: Color,
+    pub width: usize,
+    pub height: usize,
+    /// The top and the height of the text line.
+    pub line_top: usize,
+    pub line_height: usize,
+    /// In pixels per second.
+    pub marquee_speed: f32,
+    /// In seconds.
+    pub marquee_pause: f32,
+    /// Device pixels per logical one.
+    pub scale: f32,
+}
+
+/// Convert a logical length to device pixels.
+fn px(length: usize, scale: f32) -> usize {
+    (length as f32 * scale).round() as usize
+}
+
+impl Context<'_> {
+    fn px(&self, length: usize) -> usize {
+        px(length, self.scale)
+    }
+
+    fn text_width(&self, string: &str) -> usize {
+        Text::new(
+            string.to_owned(),
+            self.fonts.to_vec(),
+            self.foreground_color,
+        )
+        .get_region()
+        .0 as usize
+    }
+
+    /// Get an inline image scaled to the bar height, or `None` if it cannot be loaded.
+    fn image(&self, path: &std::path::Path) -> Option<Rc<Pixmap>> {
+        self.icons
+            .get(path, self.height)
+            .inspect_err(|e| log::warn!("Cannot load image {}: {}", path.display(), e))
+            .ok()
+    }
+
+    /// Cut `string` so that it fits in `max_width` with a trailing ellipsis.
+    fn truncate(&self, string: &str, max_width: usize) -> String {
+        let ends: Vec<_> = string
+            .char_indices()
+            .map(|(idx, ch)| idx + ch.len_utf8())
+            .collect();
+        let with_ellipsis = |end: usize| format!("{}{}", &string[..end], ELLIPSIS);
+        // Longer prefixes are never narrower, so the ones that fit come first
+        let fitting =
+            ends.partition_point(|&end| self.text_width(&with_ellipsis(end)) <= max_width);
+        match fitting {
+            0 => ELLIPSIS.to_string(),
+            n => with_ellipsis(ends[n - 1]),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub trigger: Trigger,
+    pub cmd: String,
+    /// Identifies the area, whose text may change colors while hovered.
+    pub id: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Action {
+    pub fn offset(&mut self, offset: usize) {
+        self.start += offset;
+        self.end += offset;
+    }
+
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.offset(offset);
+        self
+    }
+}
+
+enum Command {
+    Text(TextCommand),
+    Image(ImageCommand),
+    Background(LineCommand),
+    Underline(LineCommand),
+    Overline(LineCommand),
+    Rectangle(RectCommand),
+    Graph(GraphCommand),
+    Box(BoxCommand),
+    Powerline(PowerlineCommand),
+    Marquee(MarqueeCommand),
+}
+
+impl Command {
+    fn into_offset(self, offset: usize) -> Self {
+        match self {
+            Command::Text(text_command) => Command::Text(text_command.into_offset(offset)),
+            Command::Image(image_command) => Command::Image(image_command.into_offset(offset)),
+            Command::Background(line_command) => {
+                Command::Background(line_command.into_offset(offset))
+            }
+            Command::Underline(line_command) => {
+                Command::Underline(line_command.into_offset(offset))
+            }
+            Command::Overline(line_command) => Command::Overline(line_command.into_offset(offset)),
+            Command::Rectangle(rect_command) => {
+                Command::Rectangle(rect_command.into_offset(offset))
+            }
+            Command::Graph(graph_command) => Command::Graph(graph_command.into_offset(offset)),
+            Command::Box(box_command) => Command::Box(box_command.into_offset(offset)),
+            Command::Powerline(powerline_command) => {
+                Command::Powerline(powerline_command.into_offset(offset))
+            }
+            Command::Marquee(marquee_command) => {
+                Command::Marquee(marquee_command.into_offset(offset))
+            }
+        }
+    }
+
+    /// Remove `width` pixels at `at`, moving back what's after it.
+    fn cut(&mut self, at: usize, width: usize) {
+        let cut = |v: &mut usize| {
+            if *v >= at {
+                *v -= width;
+            }
+        };
+        match self {
+            Command::Text(TextCommand { start, end, .. })
+            | Command::Background(LineCommand { start, end, .. })
+            | Command::Underline(LineCommand { start, end, .. })
+            | Command::Overline(LineCommand { start, end, .. })
+            | Command::Rectangle(RectCommand { start, end, .. })
+            | Command::Box(BoxCommand { start, end, .. })
+            | Command::Powerline(PowerlineCommand { start, end, .. }) => {
+                cut(start);
+                cut(end);
+            }
+            Command::Image(ImageCommand { start, .. })
+            | Command::Graph(GraphCommand { start, .. }) => cut(start),
+            Command::Marquee(marquee) => {
+                cut(&mut marquee.start);
+                for command in &mut marquee.commands {
+                    command.cut(at, width);
+                }
+            }
+        }
+    }
+}
+
+struct LineCommand {
+    color: Color,
+    start: usize,
+    end: usize,
+}
+
+impl LineCommand {
+    pub fn offset(&mut self, offset: usize) {
+        self.start += offset;
+        self.end += offset;
+    }
+
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.offset(offset);
+        self
+    }
+}
+
+struct RectCommand {
+    color: Color,
+    start: usize,
+    end: usize,
+    top: usize,
+    height: usize,
+}
+
+impl RectCommand {
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.start += offset;
+        self.end += offset;
+        self
+    }
+}
+
+struct GraphCommand {
+    style: crate::parse::GraphStyle,
+    values: Vec<f32>,
+    color: Color,
+    start: usize,
+    width: usize,
+    top: usize,
+    height: usize,
+}
+
+impl GraphCommand {
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.start += offset;
+        self
+    }
+}
+
+struct BoxCommand {
+    style: crate::parse::BoxStyle,
+    background: Color,
+    start: usize,
+    end: usize,
+    top: usize,
+    height: usize,
+}
+
+impl BoxCommand {
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.start += offset;
+        self.end += offset;
+        self
+    }
+}
+
+struct PowerlineCommand {
+    shape: crate::parse::Powerline,
+    /// The backgrounds of the runs before and after this,
+    /// or `None` where the surface beneath shows through.
+    left: Option<Color>,
+    right: Option<Color>,
+    start: usize,
+    end: usize,
+}
+
+impl PowerlineCommand {
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.start += offset;
+        self.end += offset;
+        self
+    }
+}
+
+struct MarqueeCommand {
+    /// The text inside, to tell when the content changes.
+    key: String,
+    start: usize,
+    width: usize,
+    content_width: usize,
+    /// The content, positioned as if it weren't clipped.
+    commands: Vec<Command>,
+}
+
+impl MarqueeCommand {
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.start += offset;
+        self.commands = self
+            .commands
+            .into_iter()
+            .map(|v| v.into_offset(offset))
+            .collect();
+        self
+    }
+}
+
+/// A marquee whose end hasn't been reached yet.
+struct PendingMarquee {
+    align: Align,
+    /// Where its commands and actions start.
+    index: usize,
+    action_index: usize,
+    start: usize,
+    width: usize,
+}
+
+/// A fixed-width field whose end hasn't been reached yet.
+struct PendingField {
+    field: crate::parse::Field,
+    align: Align,
+    /// Where its commands and actions start.
+    index: usize,
+    action_index: usize,
+    start: usize,
+    /// The first action id, box and lines opened inside of it.
+    first_id: usize,
+    box_index: usize,
+    lines: (bool, bool),
+    marquee: bool,
+}
+
+/// Pick the colors of powerline separators from the backgrounds of the text runs around them.
+fn resolve_powerlines(commands: &mut [Command]) {
+    for idx in 0..commands.len() {
+        if !matches!(commands[idx], Command::Powerline(_)) {
+            continue;
+        }
+        let text_bg = |command: &Command| match command {
+            Command::Text(text) => Some(text.fill.then_some(text.bg)),
+            _ => None,
+        };
+        let left = commands[..idx].iter().rev().find_map(text_bg);
+        let right = commands[idx + 1..].iter().find_map(text_bg);
+        if let Command::Powerline(powerline) = &mut commands[idx] {
+            powerline.left = left.flatten();
+            powerline.right = right.flatten();
+        }
+    }
+}
+
+/// A box whose end hasn't been reached yet.
+struct PendingBox {
+    style: crate::parse::BoxStyle,
+    background: Color,
+    align: Align,
+    /// Where to insert the box, so it's painted before its content.
+    index: usize,
+    start: usize,
+    /// The background and the surface color outside of the box.
+    outside: (Color, Color),
+}
+
+impl PendingBox {
+    fn into_command(self, end: usize, ctx: &Context) -> BoxCommand {
+        let vertical_padding = ctx.px(self.style.padding()).min(ctx.line_top);
+        BoxCommand {
+            background: self.background,
+            start: self.start,
+            end,
+            top: ctx.line_top - vertical_padding,
+            height: ctx.line_height + vertical_padding * 2,
+            style: self.style,
+        }
+    }
+}
+
+struct TextCommand {
+    string: String,
+    fg: Color,
+    bg: Color,
+    /// Whether the background differs from what's beneath, and needs filling.
+    fill: bool,
+    /// Whether this may be cut to make room for other groups.
+    ellipsize: bool,
+    hover: Option<Hover>,
+    start: usize,
+    end: usize,
+}
+
+/// The colors of text while the pointer is over its action area.
+struct Hover {
+    id: usize,
+    fg: Color,
+    bg: Color,
+}
+
+impl TextCommand {
+    pub fn offset(&mut self, offset: usize) {
+        self.start += offset;
+        self.end += offset;
+    }
+
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.offset(offset);
+        self
+    }
+}
+
+struct ImageCommand {
+    pixmap: Rc<Pixmap>,
+    start: usize,
+}
+
+impl ImageCommand {
+    pub fn into_offset(mut self, offset: usize) -> Self {
+        self.start += offset;
+        self
+    }
+}
+
+/// The content of one alignment, positioned from 0.
+#[derive(Default)]
+struct Group {
+    commands: Vec<Command>,
+    actions: Vec<Action>,
+    /// The current position, which ends up being the width of the group.
+    cursor: usize,
+    /// Whether this contains text that may be ellipsized.
+    ellipsizable: bool,
+}
+
+/// Lay out the groups, each positioned from 0.
+fn build(ctx: &Context, data: &str) -> Result<[Group; 3], String> {
+    let mut groups: [Group; 3] = Default::default();
+    let mut fg = ctx.foreground_color;
+    let mut bg = ctx.background_color;
+    // The color already painted beneath, which text backgrounds don't need to fill
+    let mut surface = ctx.background_color;
+    let mut align = Align::Left;
+    let mut pending_action: Option<Action> = None;
+    let mut action_count = 0;
+    let mut hover_fg: Option<Color> = None;
+    let mut hover_bg: Option<Color> = None;
+    let mut pending_overline: Option<LineCommand> = None;
+    let mut pending_underline: Option<LineCommand> = None;
+    let mut boxes: Vec<PendingBox> = vec![];
+    let mut marquee: Option<PendingMarquee> = None;
+    let mut fields: Vec<PendingField> = vec![];
+    let mut ellipsis = false;
+    let mut parts = data
+        .parse::<crate::parse::StyledString>()?
+        .into_content()
+        .into_iter();
+    // Parts handled before the next ones, last first
+    let mut inserted: Vec<StyledStringPart> = vec![];
+    while let Some(part) = inserted.pop().or_else(|| parts.next()) {
+        let group = &mut groups[align as usize];
+        match part {
+            StyledStringPart::Style(style) => {
+                fg = style
+                    .foreground_color()
+                    .into_color(ctx.foreground_color, fg);
+                bg = style
+                    .background_color()
+                    .into_color(ctx.background_color, bg);
+            }
+            StyledStringPart::HoverStyle(style) => {
+                let update = |color: crate::parse::Color, hover: Option<Color>| match color {
+                    crate::parse::Color::Default => None,
+                    crate::parse::Color::Now => hover,
+                    crate::parse::Color::New(color) => Some(color),
+                };
+                hover_fg = update(style.foreground_color(), hover_fg);
+                hover_bg = update(style.background_color(), hover_bg);
+            }
+            StyledStringPart::String(string) => {
+                let width = ctx.text_width(&string);
+                // Marquees and fields keep their width whatever their content
+                let ellipsize = ellipsis && marquee.is_none() && fields.is_empty();
+                group.ellipsizable |= ellipsize;
+                let hover = pending_action
+                    .as_ref()
+                    .filter(|_| hover_fg.is_some() || hover_bg.is_some())
+                    .map(|action| Hover {
+                        id: action.id,
+                        fg: hover_fg.unwrap_or(fg),
+                        bg: hover_bg.unwrap_or(bg),
+                    });
+                group.commands.push(Command::Text(TextCommand {
+                    fg,
+                    bg,
+                    fill: bg != surface,
+                    ellipsize,
+                    hover,
+                    string,
+                    start: group.cursor,
+                    end: group.cursor + width,
+                }));
+                group.cursor += width;
+            }
+            StyledStringPart::Action(action) => {
+                let (trigger, cmd) = action.into_tuple();
+                pending_action = Some(Action {
+                    trigger,
+                    cmd,
+                    id: action_count,
+                    start: group.cursor,
+                    end: 0, // Temp
+                });
+                action_count += 1;
+            }
+            StyledStringPart::ActionEnd => {
+                (hover_fg, hover_bg) = (None, None);
+                if let Some(pending) = pending_action.take() {
+                    group.actions.push(Action {
+                        end: group.cursor,
+                        ..pending
+                    });
+                }
+            }
+            StyledStringPart::Swap => {
+                std::mem::swap(&mut fg, &mut bg);
+            }
+            StyledStringPart::Align(align_) => {
+                if pending_action.is_some() {
+                    log::error!("Cannot change align in actions!");
+                    continue;
+                }
+                align = align_;
+            }
+            StyledStringPart::Offset(offset) => {
+                let offset = ctx.px(offset);
+                // Gaps inside a colored region take its color
+                if bg != surface {
+                    group.commands.push(Command::Background(LineCommand {
+                        color: bg,
+                        start: group.cursor,
+                        end: group.cursor + offset,
+                    }));
+                }
+                group.cursor += offset;
+            }
+            StyledStringPart::Image(path) => {
+                let Some(pixmap) = ctx.image(&path) else {
+                    continue;
+                };
+                let width = pixmap.width();
+                group.commands.push(Command::Image(ImageCommand {
+                    pixmap,
+                    start: group.cursor,
+                }));
+                group.cursor += width;
+            }
+            StyledStringPart::Progress(progress) => {
+                let progress_width = ctx.px(progress.width());
+                let filled = (progress_width as f32 * progress.ratio()).round() as usize;
+                group.commands.push(Command::Rectangle(RectCommand {
+                    color: progress.background_color().into_color(bg, bg),
+                    start: group.cursor + filled,
+                    end: group.cursor + progress_width,
+                    top: ctx.line_top,
+                    height: ctx.line_height,
+                }));
+                group.commands.push(Command::Rectangle(RectCommand {
+                    color: progress.foreground_color().into_color(fg, fg),
+                    start: group.cursor,
+                    end: group.cursor + filled,
+                    top: ctx.line_top,
+                    height: ctx.line_height,
+                }));
+                group.cursor += progress_width;
+            }
+            StyledStringPart::Rectangle(rectangle) => {
+                let rect_width = ctx.px(rectangle.width());
+                let rect_height = rectangle.height().map_or(ctx.line_height, |v| ctx.px(v));
+                group.commands.push(Command::Rectangle(RectCommand {
+                    color: rectangle.color().into_color(fg, fg),
+                    start: group.cursor,
+                    end: group.cursor + rect_width,
+                    top: ctx.height.saturating_sub(rect_height) / 2,
+                    height: rect_height,
+                }));
+                group.cursor += rect_width;
+            }
+            StyledStringPart::Separator(separator) => {
+                let thickness = ctx.px(separator.thickness());
+                group.commands.push(Command::Rectangle(RectCommand {
+                    color: separator.color().into_color(fg, fg),
+                    start: group.cursor,
+                    end: group.cursor + thickness,
+                    top: ctx.line_top,
+                    height: ctx.line_height,
+                }));
+                group.cursor += thickness;
+            }
+            StyledStringPart::Graph(graph) => {
+                let style = graph.style();
+                let graph_width = ctx.px(graph.width());
+                let values = match graph.name() {
+                    Some(name) => ctx
+                        .series
+                        .get(name)
+                        .map(|v| v.iter().copied().collect())
+                        .unwrap_or_default(),
+                    None => graph.into_values(),
+                };
+                if bg != surface {
+                    group.commands.push(Command::Background(LineCommand {
+                        color: bg,
+                        start: group.cursor,
+                        end: group.cursor + graph_width,
+                    }));
+                }
+                group.commands.push(Command::Graph(GraphCommand {
+                    style,
+                    values,
+                    color: fg,
+                    start: group.cursor,
+                    width: graph_width,
+                    top: ctx.line_top,
+                    height: ctx.line_height,
+                }));
+                group.cursor += graph_width;
+            }
+            StyledStringPart::Powerline(shape) => {
+                let powerline_width = ctx.height / 2;
+                group.commands.push(Command::Powerline(PowerlineCommand {
+                    shape,
+                    left: None,
+                    right: None,
+                    start: group.cursor,
+                    end: group.cursor + powerline_width,
+                }));
+                group.cursor += powerline_width;
+            }
+            StyledStringPart::BoxStart(style) => {
+                let background = style
+                    .background_color()
+                    .into_color(ctx.background_color, bg);
+                let padding = ctx.px(style.padding());
+                boxes.push(PendingBox {
+                    style,
+                    background,
+                    align,
+                    index: group.commands.len(),
+                    start: group.cursor,
+                    outside: (bg, surface),
+                });
+                group.cursor += padding;
+                bg = background;
+                surface = background;
+            }
+            StyledStringPart::BoxEnd => {
+                let Some(pending) = boxes.pop() else {
+                    log::warn!("Unopened box; check your feeding script");
+                    continue;
+                };
+                (bg, surface) = pending.outside;
+                close_box(&mut groups[pending.align as usize], pending, ctx);
+            }
+            StyledStringPart::MarqueeStart(width) => {
+                if marquee.is_some() {
+                    log::error!("Cannot nest marquees!");
+                    continue;
+                }
+                marquee = Some(PendingMarquee {
+                    align,
+                    index: group.commands.len(),
+                    action_index: group.actions.len(),
+                    start: group.cursor,
+                    width: ctx.px(width),
+                });
+            }
+            StyledStringPart::MarqueeEnd => {
+                // Fields opened inside the marquee are closed with it
+                if marquee.is_some() && fields.last().is_some_and(|v| !v.marquee) {
+                    log::warn!("Unclosed field in marquee; check your feeding script");
+                    inserted.extend([StyledStringPart::MarqueeEnd, StyledStringPart::FieldEnd]);
+                    continue;
+                }
+                let Some(pending) = marquee.take() else {
+                    log::warn!("Unopened marquee; check your feeding script");
+                    continue;
+                };
+                close_marquee(&mut groups[pending.align as usize], pending);
+            }
+            StyledStringPart::FieldStart(field) => {
+                fields.push(PendingField {
+                    field,
+                    align,
+                    index: group.commands.len(),
+                    action_index: group.actions.len(),
+                    start: group.cursor,
+                    first_id: action_count,
+                    box_index: boxes.len(),
+                    lines: (pending_underline.is_none(), pending_overline.is_none()),
+                    marquee: marquee.is_none(),
+                });
+            }
+            StyledStringPart::FieldEnd => {
+                let Some(pending) = fields.pop() else {
+                    log::warn!("Unopened field; check your feeding script");
+                    continue;
+                };
+                let &PendingField {
+                    align: field_align,
+                    first_id,
+                    box_index,
+                    lines: (underline, overline),
+                    marquee: inner_marquee,
+                    ..
+                } = &pending;
+                let shift = close_field(&mut groups[field_align as usize], pending, ctx);
+                // Blocks opened inside the field and still open move with its content
+                let mut starts: Vec<&mut usize> = vec![];
+                if field_align == align {
+                    starts.extend(
+                        pending_action
+                            .as_mut()
+                            .filter(|v| v.id >= first_id)
+                            .map(|v| &mut v.start),
+                    );
+                    starts.extend(
+                        pending_underline
+                            .as_mut()
+                            .filter(|_| underline)
+                            .map(|v| &mut v.start),
+                    );
+                    starts.extend(
+                        pending_overline
+                            .as_mut()
+                            .filter(|_| overline)
+                            .map(|v| &mut v.start),
+                    );
+                }
+                starts.extend(
+                    boxes
+                        .iter_mut()
+                        .skip(box_index)
+                        .filter(|v| v.align == field_align)
+                        .map(|v| &mut v.start),
+                );
+                starts.extend(
+                    marquee
+                        .as_mut()
+                        .filter(|v| inner_marquee && v.align == field_align)
+                        .map(|v| &mut v.start),
+                );
+                for start in starts {
+                    *start += shift;
+                }
+            }
+            StyledStringPart::Attribute {
+                attribute: Attribute::Ellipsis,
+                action,
+            } => {
+                ellipsis = match action {
+                    AttributeAction::On => true,
+                    AttributeAction::Off => false,
+                    AttributeAction::Toggle => !ellipsis,
+                };
+            }
+            StyledStringPart::Attribute { attribute, action } => {
+                let pending = if attribute == Attribute::Underline {
+                    &mut pending_underline
+                } else {
+                    &mut pending_overline
+                };
+                let on = match action {
+                    AttributeAction::On => true,
+                    AttributeAction::Off => false,
+                    AttributeAction::Toggle => pending.is_none(),
+                };
+                if on {
+                    pending.get_or_insert(LineCommand {
+                        color: ctx.foreground_color,
+                        start: group.cursor,
+                        end: 0, // Temp
+                    });
+                } else if let Some(line) = pending.take() {
+                    let line = LineCommand {
+                        end: group.cursor,
+                        ..line
+                    };
+                    group.commands.push(if attribute == Attribute::Underline {
+                        Command::Underline(line)
+                    } else {
+                        Command::Overline(line)
+                    });
+                }
+            }
+        }
+    }
+    if let Some(pending) = pending_action {
+        log::warn!("Unclosed action block; check your feeding script");
+        let group = &mut groups[align as usize];
+        group.actions.push(Action {
+            end: group.cursor,
+            ..pending
+        });
+    }
+    while let Some(pending) = boxes.pop() {
+        log::warn!("Unclosed box; check your feeding script");
+        close_box(&mut groups[pending.align as usize], pending, ctx);
+    }
+    while let Some(pending) = fields.pop() {
+        log::warn!("Unclosed field; check your feeding script");
+        let inner_marquee = pending.marquee;
+        let field_align = pending.align;
+        let shift = close_field(&mut groups[field_align as usize], pending, ctx);
+        if let Some(marquee) = marquee
+            .as_mut()
+            .filter(|v| inner_marquee && v.align == field_align)
+        {
+            marquee.start += shift;
+        }
+    }
+    if let Some(pending) = marquee {
+        log::warn!("Unclosed marquee; check your feeding script");
+        close_marquee(&mut groups[pending.align as usize], pending);
+    }
+    for group in &mut groups {
+        resolve_powerlines(&mut group.commands);
+    }
+    Ok(groups)
+}
+
+fn close_box(group: &mut Group, pending: PendingBox, ctx: &Context) {
+    group.cursor += ctx.px(pending.style.padding());
+    // Boxes opened inside a closed marquee end up around it
+    let index = pending.index.min(group.commands.len());
+    group
+        .commands
+        .insert(index, Command::Box(pending.into_command(group.cursor, ctx)));
+}
+
+/// Align the content of a field inside it, and reserve its width.
+///
+/// Content wider than the field widens it. Returns how far its content moved.
+fn close_field(group: &mut Group, pending: PendingField, ctx: &Context) -> usize {
+    let width = match pending.field.width() {
+        FieldWidth::Pixels(width) => ctx.px(width),
+        FieldWidth::Chars(count) => count * ctx.text_width("0"),
+    };
+    let content_width = group.cursor.saturating_sub(pending.start);
+    let shift = match pending.field.align() {
+        Align::Left => 0,
+        Align::Center => width.saturating_sub(content_width) / 2,
+        Align::Right => width.saturating_sub(content_width),
+    };
+    let commands = group
+        .commands
+        .split_off(pending.index.min(group.commands.len()));
+    group
+        .commands
+        .extend(commands.into_iter().map(|v| v.into_offset(shift)));
+    let action_index = pending.action_index.min(group.actions.len());
+    for action in &mut group.actions[action_index..] {
+        if action.start >= pending.start {
+            action.start += shift;
+        }
+        action.end += shift;
+    }
+    group.cursor = pending.start + content_width.max(width);
+    shift
+}
+
+/// Move the content of a marquee into its own command, and keep only its visible width.
+fn close_marquee(group: &mut Group, pending: PendingMarquee) {
+    let content_width = group.cursor - pending.start;
+    let commands: Vec<Command> = group.commands.drain(pending.index..).collect();
+    let key = commands
+        .iter()
+        .filter_map(|v| match v {
+            Command::Text(text) => Some(text.string.as_str()),
+            _ => None,
+        })
+        .collect();
+    let width = content_width.min(pending.width);
+    group.cursor = pending.start + width;
+    for action in &mut group.actions[pending.action_index..] {
+        action.start = action.start.min(group.cursor);
+        action.end = action.end.min(group.cursor);
+    }
+    group.commands.push(Command::Marquee(MarqueeCommand {
+        key,
+        start: pending.start,
+        width,
+        content_width,
+        commands,
+    }));
+}
+
+/// Get where the left, center and right groups start, and the range each is clipped to.
+///
+/// The right group wins over the left one, which wins over the center one.
+fn bounds(
+    width: usize,
+    padding: usize,
+    [left, center, right]: [usize; 3],
+) -> [(usize, usize, usize); 3] {
+    let right_start = width
+        .saturating_sub(padding + right)
+        .max(padding.min(width));
+    let left_end = (padding + left).min(right_start);
+    let center_start = (width.saturating_sub(center) / 2).max(left_end);
+    [
+        (padding, 0, right_start),
+        (center_start, center_start, right_start.max(center_start)),
+        (right_start, right_start, width),
+    ]
+}
+
+/// Get how much each group overflows the room left by the groups winning over it.
+fn overflow(width: usize, padding: usize, groups: &[Group; 3]) -> [usize; 3] {
+    let [left, center, right] = [0, 1, 2].map(|v| groups[v].cursor);
+    let right_excess = right.saturating_sub(width.saturating_sub(padding * 2));
+    let right_start = width.saturating_sub(padding + right - right_excess);
+    let left_excess = (padding + left).saturating_sub(right_start);
+    let left_end = (padding + left).min(right_start);
+    let center_excess = center.saturating_sub(right_start.saturating_sub(left_end));
+    let mut retval = [left_excess, center_excess, right_excess];
+    for (excess, group) in retval.iter_mut().zip(groups) {
+        if !group.ellipsizable {
+            *excess = 0;
+        }
+    }
+    retval
+}
+
+/// Shrink the ellipsizable text of `group` by up to `budget`, from the first one on.
+fn ellipsize(ctx: &Context, group: &mut Group, mut budget: usize) {
+    for idx in 0..group.commands.len() {
+        if budget == 0 {
+            break;
+        }
+        let Command::Text(text) = &mut group.commands[idx] else {
+            continue;
+        };
+        if !text.ellipsize {
+            continue;
+        }
+        let width = text.end - text.start;
+        let string = ctx.truncate(&text.string, width.saturating_sub(budget));
+        let removed = width.saturating_sub(ctx.text_width(&string));
+        if removed == 0 {
+            continue;
+        }
+        text.string = string;
+        let at = text.end;
+        budget = budget.saturating_sub(removed);
+        for command in &mut group.commands {
+            command.cut(at, removed);
+        }
+        for action in &mut group.actions {
+            for v in [&mut action.start, &mut action.end] {
+                if *v >= at {
+                    *v -= removed;
+                }
+            }
+        }
+        group.cursor -= removed;
+    }
+}
+
+/// A group placed on the bar.
+struct Placed {
+    commands: Vec<Command>,
+    /// The range this is painted in; commands are relative to its start.
+    clip_start: usize,
+    clip_end: usize,
+}
+
+/// The bar content positioned on the bar.
+#[derive(Default)]
+pub struct Layout {
+    groups: Vec<Placed>,
+    actions: Vec<Action>,
+    fonts: Vec<Face>,
+    width: usize,
+    height: usize,
+    line_top: usize,
+    line_height: usize,
+    marquee_speed: f32,
+    marquee_pause: f32,
+    scale: f32,
+}
+
+impl Layout {
+    /// Lay out `data`, ellipsizing or clipping groups which would overlap each other.
+    pub fn new(ctx: &Context, data: &str) -> Result<Self, String> {
+        let mut groups = build(ctx, data)?;
+        let padding = ctx.px(EDGE_PADDING);
+        let budgets = overflow(ctx.width, padding, &groups);
+        for (group, budget) in groups.iter_mut().zip(budgets) {
+            ellipsize(ctx, group, budget);
+        }
+        let widths = [0, 1, 2].map(|v| groups[v].cursor);
+        let mut placed = vec![];
+        let mut actions = vec![];
+        for (group, (start, clip_start, clip_end)) in
+            groups.into_iter().zip(bounds(ctx.width, padding, widths))
+        {
+            actions.extend(group.actions.into_iter().filter_map(|v| {
+                let v = v.into_offset(start);
+                let action = Action {
+                    start: v.start.max(clip_start),
+                    end: v.end.min(clip_end),
+                    ..v
+                };
+                (action.start < action.end).then_some(action)
+            }));
+            placed.push(Placed {
+                commands: group
+                    .commands
+                    .into_iter()
+                    .map(|v| v.into_offset(start - clip_start))
+                    .collect(),
+                clip_start,
+                clip_end,
+            });
+        }
+        Ok(Self {
+            groups: placed,
+            actions,
+            fonts: ctx.fonts.to_vec(),
+            width: ctx.width,
+            height: ctx.height,
+            line_top: ctx.line_top,
+            line_height: ctx.line_height,
+            marquee_speed: ctx.marquee_speed * ctx.scale,
+            marquee_pause: ctx.marquee_pause,
+            scale: ctx.scale,
+        })
+    }
+
+    /// Get how wide `data` is when nothing is cut, edge padding included.
+    pub fn measure(ctx: &Context, data: &str) -> Result<usize, String> {
+        let groups = build(ctx, data)?;
+        Ok(groups.iter().map(|v| v.cursor).sum::<usize>() + ctx.px(EDGE_PADDING) * 2)
+    }
+
+    /// Get the bar size this was laid out for.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn marquees(&self) -> impl Iterator<Item = &MarqueeCommand> {
+        self.groups
+            .iter()
+            .flat_map(|v| &v.commands)
+            .filter_map(|v| match v {
+                Command::Marquee(marquee) => Some(marquee),
+                _ => None,
+            })
+    }
+
+    /// Whether this has to be repainted as time goes, for scrolling marquees.
+    pub fn is_animated(&self) -> bool {
+        self.marquees().any(|v| v.content_width > v.width)
+    }
+
+    /// Get the text in each marquee, which restarts scrolling when changed.
+    pub fn marquee_keys(&self) -> Vec<String> {
+        self.marquees().map(|v| v.key.clone()).collect()
+    }
+
+    /// Get how far a marquee overflowing by `distance` is scrolled after `elapsed`.
+    fn scroll(&self, distance: usize, elapsed: Duration) -> usize {
+        if distance == 0 {
+            return 0;
+        }
+        let speed = self.marquee_speed.max(f32::EPSILON);
+        let pause = self.marquee_pause.max(0.);
+        let cycle = pause * 2. + distance as f32 / speed;
+        let t = elapsed.as_secs_f32() % cycle;
+        ((t - pause) * speed).clamp(0., distance as f32) as usize
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Whether a binding needs modifier keys, which only come with the keyboard.
+    pub fn needs_modifiers(&self) -> bool {
+        self.actions.iter().any(|v| {
+            matches!(
+                v.trigger,
+                Trigger::Button(_, m) | Trigger::Menu(_, m) if m != Modifiers::default()
+            )
+        })
+    }
+
+    /// Get the topmost action area at `x` run by `button` with exactly `modifiers` held,
+    /// and its number.
+    pub fn click(&self, x: usize, button: u8, modifiers: Modifiers) -> Option<(usize, &Action)> {
+        self.actions.iter().enumerate().rev().find(|(_, v)| {
+            (v.start..v.end).contains(&x)
+                && matches!(
+                    v.trigger,
+                    Trigger::Button(b, m) | Trigger::Menu(b, m) if b == button && m == modifiers
+                )
+        })
+    }
+
+    /// Paint this, with marquees scrolled as `elapsed` since their content changed,
+    /// and the action areas of `hovered` in their hover colors.
+    pub fn paint(
+        &self,
+        canvas: &mut impl Paintable,
+        elapsed: Duration,
+        hovered: &[usize],
+    ) -> Result<(), Error> {
+        for group in &self.groups {
+            let clip_end = group.clip_end.min(canvas.width());
+            if group.clip_start >= clip_end {
+                continue;
+            }
+            let height = canvas.height();
+            let mut canvas =
+                canvas.slice(group.clip_start, 0, clip_end - group.clip_start, height)?;
+            for command in &group.commands {
+                match command {
+                    Command::Marquee(marquee) => {
+                        self.paint_marquee(marquee, &mut canvas, elapsed, hovered)?
+                    }
+                    command => self.paint_command(command, &mut canvas, hovered)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn paint_marquee(
+        &self,
+        marquee: &MarqueeCommand,
+        canvas: &mut impl Paintable,
+        elapsed: Duration,
+        hovered: &[usize],
+    ) -> Result<(), Error> {
+        if marquee.start >= canvas.width() {
+            return Ok(());
+        }
+        let scroll = self.scroll(marquee.content_width - marquee.width, elapsed);
+        let width = marquee.width.min(canvas.width() - marquee.start);
+        let height = canvas.height();
+        let mut canvas = canvas
+            .slice(marquee.start, 0, width, height)?
+            .scrolled(marquee.start + scroll);
+        for command in &marquee.commands {
+            self.paint_command(command, &mut canvas, hovered)?;
+        }
+        Ok(())
+    }
+
+    fn paint_command(
+        &self,
+        command: &Command,
+        canvas: &mut impl Paintable,
+        hovered: &[usize],
+    ) -> Result<(), Error> {
+        let height = self.height.min(canvas.height());
+        match command {
+            Command::Text(command) => {
+                let TextCommand {
+                    string,
+                    fg,
+                    bg,
+                    fill,
+                    hover,
+                    start,
+                    end,
+                    ..
+                } = command;
+                let (start, end) = (*start, *end);
+                let (fg, bg, fill) = match hover {
+                    Some(hover) if hovered.contains(&hover.id) => (&hover.fg, &hover.bg, true),
+                    _ => (fg, bg, *fill),
+                };
+
+                if fill {
+                    canvas.fill_rect(start, 0, end - start, height, *bg)?;
+                }
+                if start >= canvas.width() || self.line_top >= canvas.height() {
+                    return Ok(());
+                }
+
+                let text = Text::new(string.clone(), self.fonts.clone(), *fg);
+                let (width, height) = (canvas.width(), canvas.height());
+                text.paint(&mut canvas.slice(
+                    start,
+                    self.line_top,
+                    width - start,
+                    height - self.line_top,
+                )?)?;
+            }
+            Command::Image(command) => {
+                let ImageCommand { pixmap, start } = command;
+                let start = *start;
+
+                if start < canvas.width() {
+                    let width = pixmap.width().min(canvas.width() - start);
+                    pixmap.paint(&mut canvas.slice(
+                        start,
+                        0,
+                        width,
+                        pixmap.height().min(height),
+                    )?)?;
+                }
+            }
+            Command::Background(command) => {
+                let LineCommand { color, start, end } = command;
+
+                canvas.fill_rect(*start, 0, end - start, height, *color)?;
+            }
+            Command::Underline(command) => {
+                let LineCommand { color, start, end } = command;
+
+                let thickness = px(1, self.scale).max(1);
+                canvas.blend_rect(
+                    *start,
+                    self.line_top + self.line_height + thickness,
+                    end.saturating_sub(*start),
+                    thickness,
+                    *color,
+                )?;
+            }
+            Command::Overline(command) => {
+                let LineCommand { color, start, end } = command;
+
+                let thickness = px(1, self.scale).max(1);
+                canvas.blend_rect(
+                    *start,
+                    self.line_top.saturating_sub(thickness),
+                    end.saturating_sub(*start),
+                    thickness,
+                    *color,
+                )?;
+            }
+            Command::Graph(command) => {
+                let GraphCommand {
+                    style,
+                    values,
+                    color,
+                    start,
+                    width,
+                    top,
+                    height,
+                } = command;
+                let (start, width, top, height) = (*start, *width, *top, *height);
+
+                let max = values.iter().copied().fold(f32::EPSILON, f32::max);
+                let scaled = values.iter().map(|v| v.max(0.) / max * height as f32);
+                match style {
+                    crate::parse::GraphStyle::Line => {
+                        let step = (width as f32 - 1.) / (values.len() as f32 - 1.).max(1.);
+                        let points: Vec<_> = scaled
+                            .enumerate()
+                            .map(|(idx, v)| {
+                                (
+                                    start as f32 + idx as f32 * step,
+                                    (top + height) as f32 - 1. - v.min(height as f32 - 1.),
+                                )
+                            })
+                            .collect();
+                        if let [(_, y)] = points[..] {
+                            // A single sample has no segment to draw
+                            let x = (start + width / 2).saturating_sub(1);
+                            let y = (y as usize).saturating_sub(1).max(top);
+                            canvas.blend_rect(x, y, 2.min(width), 2.min(height), *color)?;
+                        }
+                        for pair in points.windows(2) {
+                            canvas.draw_line(pair[0], pair[1], *color)?;
+                        }
+                    }
+                    crate::parse::GraphStyle::Bars => {
+                        let step = width as f32 / values.len().max(1) as f32;
+                        for (idx, v) in scaled.enumerate() {
+                            let bar_start = start + (idx as f32 * step) as usize;
+                            let bar_end = start + ((idx + 1) as f32 * step) as usize;
+                            let bar_height = v.round() as usize;
+                            canvas.blend_rect(
+                                bar_start,
+                                top + height - bar_height,
+                                bar_end - bar_start,
+                                bar_height,
+                                *color,
+                            )?;
+                        }
+                    }
+                }
+            }
+            Command::Powerline(command) => {
+                let PowerlineCommand {
+                    shape,
+                    left,
+                    right,
+                    start,
+                    end,
+                } = command;
+                let start = *start;
+
+                let width = end - start;
+                let (color, backdrop) = match shape {
+                    crate::parse::Powerline::ArrowRight | crate::parse::Powerline::RoundRight => {
+                        (*left, *right)
+                    }
+                    crate::parse::Powerline::ArrowLeft | crate::parse::Powerline::RoundLeft => {
+                        (*right, *left)
+                    }
+                };
+                let arrow = crate::paint::arrow_right(width as f32, height as f32);
+                let round = crate::paint::half_circle_right(height as f32);
+                let mirror = width as f32;
+                let coverage = |x: f32, y: f32| match shape {
+                    crate::parse::Powerline::ArrowRight => arrow(x, y),
+                    crate::parse::Powerline::ArrowLeft => arrow(mirror - x, y),
+                    crate::parse::Powerline::RoundRight => round(x, y),
+                    crate::parse::Powerline::RoundLeft => round(mirror - x, y),
+                };
+                // Sides without a background of their own keep what's painted beneath
+                match (color, backdrop) {
+                    (Some(color), Some(backdrop)) => {
+                        canvas.fill_rect(start, 0, width, height, backdrop)?;
+                        canvas.fill_shape(start, 0, width, height, color, coverage)?;
+                    }
+                    (Some(color), None) => {
+                        canvas.fill_shape(start, 0, width, height, color, coverage)?;
+                    }
+                    (None, Some(backdrop)) => {
+                        canvas.fill_shape(start, 0, width, height, backdrop, |x, y| {
+                            1. - coverage(x, y)
+                        })?;
+                    }
+                    (None, None) => {}
+                }
+            }
+            Command::Box(command) => {
+                let BoxCommand {
+                    style,
+                    background,
+                    start,
+                    end,
+                    top,
+                    height,
+                } = command;
+                let (start, top, height) = (*start, *top, *height);
+
+                let width = end - start;
+                let radius = style.radius() as f32 * self.scale;
+                let (border_width, border_color) = style
+                    .border()
+                    .map(|(width, color)| (px(width, self.scale), color))
+                    .unwrap_or((0, Color::new(0, 0, 0, 0)));
+                let inner_width = width.saturating_sub(border_width * 2);
+                let inner_height = height.saturating_sub(border_width * 2);
+                let inner = crate::paint::rounded_rect(
+                    inner_width as f32,
+                    inner_height as f32,
+                    radius - border_width as f32,
+                );
+                canvas.fill_shape(
+                    start + border_width,
+                    top + border_width,
+                    inner_width,
+                    inner_height,
+                    *background,
+                    &inner,
+                )?;
+                if border_width != 0 {
+                    let outer = crate::paint::rounded_rect(width as f32, height as f32, radius);
+                    let border_width = border_width as f32;
+                    canvas.fill_shape(start, top, width, height, border_color, |x, y| {
+                        outer(x, y) - inner(x - border_width, y - border_width)
+                    })?;
+                }
+            }
+            Command::Rectangle(command) => {
+                let RectCommand {
+                    color,
+                    start,
+                    end,
+                    top,
+                    height,
+                } = command;
+
+                canvas.blend_rect(*start, *top, end.saturating_sub(*start), *height, *color)?;
+            }
+            Command::Marquee(_) => {} // Marquees don't nest; painted by `paint_marquee`
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::BitmapFont;
+    use crate::paint::Canvas;
+
+    const WIDTH: usize = 100;
+    const HEIGHT: usize = 20;
+
+    /// A bitmap font whose glyphs are all 6 pixels wide, so text widths are known.
+    fn font() -> Face {
+        let mut bdf = String::from("STARTFONT 2.1\nFONTBOUNDINGBOX 6 10 0 -2\n");
+        for ch in (' '..='~').chain([ELLIPSIS]) {
+            bdf += &format!(
+                "STARTCHAR x\nENCODING {}\nDWIDTH 6 0\nBBX 6 1 0 0\nBITMAP\nFC\nENDCHAR\n",
+                ch as u32
+            );
+        }
+        bdf += "ENDFONT\n";
+        Face::Bitmap(Rc::new(BitmapFont::from_data(bdf.as_bytes()).unwrap()))
+    }
+
+    fn with_context<T>(f: impl FnOnce(&Context) -> T) -> T {
+        let fonts = [font()];
+        f(&Context {
+            fonts: &fonts,
+            icons: &IconCache::default(),
+            series: &HashMap::new(),
+            foreground_color: Color::new(255, 255, 255, 255),
+            background_color: Color::new(0, 0, 0, 255),
+            width: WIDTH,
+            height: HEIGHT,
+            line_top: 2,
+            line_height: 16,
+            marquee_speed: 30.,
+            marquee_pause: 1.,
+            scale: 1.,
+        })
+    }
+
+    fn layout(data: &str) -> Layout {
+        with_context(|ctx| Layout::new(ctx, data).unwrap())
+    }
+
+    fn texts(layout: &Layout) -> Vec<&str> {
+        layout
+            .groups
+            .iter()
+            .flat_map(|v| &v.commands)
+            .filter_map(|v| match v {
+                Command::Text(text) => Some(text.string.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn action_ranges(layout: &Layout) -> Vec<(usize, usize)> {
+        layout.actions().iter().map(|v| (v.start, v.end)).collect()
+    }
+
+    /// Paint `data` on a transparent canvas and get the color at each point.
+    fn paint(data: &str, points: &[(usize, usize)]) -> Vec<Color> {
+        let mut buffer = vec![0; WIDTH * HEIGHT * 4];
+        let mut canvas = Canvas::new(HEIGHT, WIDTH, &mut buffer);
+        layout(data)
+            .paint(&mut canvas, Duration::ZERO, &[])
+            .unwrap();
+        points
+            .iter()
+            .map(|&(x, y)| canvas.get_pixel(x, y).unwrap())
+            .collect()
+    }
+
+    /// Compare colors, allowing for the rounding of blending.
+    fn assert_near<const N: usize>(colors: Vec<Color>, expected: [Color; N]) {
+        let bytes = |v: &Color| <[u8; 4]>::from(v);
+        let near = colors.len() == N
+            && colors.iter().zip(&expected).all(|(a, b)| {
+                bytes(a)
+                    .iter()
+                    .zip(bytes(b))
+                    .all(|(a, b)| a.abs_diff(b) <= 3)
+            });
+        assert!(near, "{colors:?} != {expected:?}");
+    }
+
+    #[test]
+    fn line_graph_with_one_sample_draws_a_dot() {
+        let white = Color::new(255, 255, 255, 255);
+        assert_eq!(paint("%{G:10:1}", &[(9, 2)]), [white]);
+    }
+
+    #[test]
+    fn graph_fills_its_background() {
+        let red = Color::new(255, 0, 0, 255);
+        assert_eq!(paint("%{B#ff0000}%{Gb:10:0,0}", &[(6, 10)]), [red]);
+        assert_eq!(paint("%{Gb:10:0,0}", &[(6, 10)]), [Color::new(0, 0, 0, 0)]);
+    }
+
+    #[test]
+    fn powerline_keeps_the_surface_beneath_plain_sides() {
+        let red = Color::new(255, 0, 0, 255);
+        let clear = Color::new(0, 0, 0, 0);
+        // The arrow covers 17..27, after two characters
+        let points = [(20, 10), (26, 0)];
+        assert_near(paint("%{B#ff0000}ab%{>}", &points), [red, clear]);
+        assert_near(
+            paint("%{>}%{B#ff0000}ab", &[(8, 10), (14, 0)]),
+            [clear, red],
+        );
+        assert_near(paint("ab%{>}%{B#ff0000}ab", &points), [clear, red]);
+    }
+
+    #[test]
+    fn truncate_keeps_the_longest_fitting_prefix() {
+        with_context(|ctx| {
+            assert_eq!(ctx.truncate("abcdef", 24), "abc…");
+            assert_eq!(ctx.truncate("abcdef", 29), "abc…");
+            assert_eq!(ctx.truncate("abcdef", 42), "abcdef…");
+            assert_eq!(ctx.truncate("abc", 5), "…");
+        });
+    }
+
+    #[test]
+    fn ellipsized_text_moves_back_what_follows() {
+        // 120 pixels of text and an action don't fit in the 90 between the edges
+        let layout = layout(&format!("%{{+e}}{}%{{-e}}%{{A:x:}}b%{{A}}", "a".repeat(20)));
+        assert_eq!(
+            texts(&layout),
+            [format!("{}…", "a".repeat(13)).as_str(), "b"]
+        );
+        assert_eq!(action_ranges(&layout), [(89, 95)]);
+    }
+
+    #[test]
+    fn fitting_text_is_not_ellipsized() {
+        let layout = layout("%{+e}abc%{-e}");
+        assert_eq!(texts(&layout), ["abc"]);
+    }
+
+    #[test]
+    fn right_aligned_field_moves_actions_opened_inside() {
+        // The field spans 5..55, with its content shifted by 38
+        let ranges = |data| action_ranges(&layout(data));
+        assert_eq!(ranges("%{W50:r}%{A:x:}ab%{A}%{W}"), [(43, 55)]);
+        assert_eq!(ranges("%{W50:r}%{A:x:}ab%{W}cd%{A}"), [(43, 67)]);
+        assert_eq!(ranges("%{A:x:}%{W50:r}ab%{W}%{A}"), [(5, 55)]);
+    }
+
+    #[test]
+    fn right_aligned_field_moves_lines_opened_inside() {
+        let layout = layout("%{W50:r}%{+u}ab%{W}cd%{-u}");
+        let underlines: Vec<_> = layout.groups[0]
+            .commands
+            .iter()
+            .filter_map(|v| match v {
+                Command::Underline(line) => Some((line.start, line.end)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(underlines, [(43, 67)]);
+    }
+
+    #[test]
+    fn clicks_need_the_exact_modifiers() {
+        let layout = layout("%{AS1:shifted:}ab%{A}%{A3:plain:}cd%{A}");
+        let shift = Modifiers {
+            shift: true,
+            ..Default::default()
+        };
+        let none = Modifiers::default();
+        let click = |x, button, modifiers| {
+            layout
+                .click(x, button, modifiers)
+                .map(|(_, v)| v.cmd.as_str())
+        };
+        assert_eq!(click(6, 1, shift), Some("shifted"));
+        assert_eq!(click(6, 1, none), None);
+        assert_eq!(
+            click(
+                6,
+                1,
+                Modifiers {
+                    ctrl: true,
+                    ..shift
+                }
+            ),
+            None
+        );
+        assert_eq!(click(20, 3, none), Some("plain"));
+        assert_eq!(click(20, 3, shift), None);
+        assert_eq!(click(20, 1, none), None);
+    }
+
+    #[test]
+    fn marquees_clip_to_their_width() {
+        let layout = layout("%{M12}%{A:x:}abcd%{A}%{M}%{A:y:}e%{A}");
+        assert!(layout.is_animated());
+        assert_eq!(layout.marquee_keys(), ["abcd"]);
+        assert_eq!(action_ranges(&layout), [(5, 17), (17, 23)]);
+        assert!(!self::layout("%{M30}ab%{M}").is_animated());
+    }
+
+    #[test]
+    fn marquees_scroll_between_pauses() {
+        // 12 pixels past the width take 0.4s at 30 pixels a second, with 1s pauses at each end
+        let layout = layout("%{M12}abcd%{M}");
+        let scroll = |secs| layout.scroll(12, Duration::from_secs_f32(secs));
+        assert_eq!(scroll(0.5), 0);
+        assert_eq!(scroll(1.2), 6);
+        assert_eq!(scroll(2.), 12);
+        assert_eq!(scroll(2.5), 0);
+        assert_eq!(layout.scroll(0, Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn fields_opened_in_a_marquee_close_with_it() {
+        // The field closes at the end of the marquee, so "ij" follows the 5 pixel marquee,
+        // and the action in the field is hidden past its width
+        let layout = layout("%{M5}abcdef%{W50}%{A:x:}gh%{A}%{M}ij%{W}%{A:y:}k%{A}");
+        assert_eq!(layout.marquee_keys(), ["abcdefgh"]);
+        assert_eq!(texts(&layout), ["ij", "k"]);
+        assert_eq!(action_ranges(&layout), [(22, 28)]);
+        // Nothing is left to close at the stray field end
+        assert!(texts(&self::layout("%{M5}abcdef%{W50}gh%{M}%{W}")).is_empty());
+        // Marquees opened in a field and closed after it keep their content
+        let layout = self::layout("%{W50}%{M5}abcdef%{W}gh%{M}");
+        assert_eq!(layout.marquee_keys(), ["abcdefgh"]);
+    }
+
+    #[test]
+    fn modifiers_are_needed_by_modified_bindings_only() {
+        assert!(layout("%{AS1:x:}ab%{A}").needs_modifiers());
+        assert!(layout("%{AmC3:x:}ab%{A}").needs_modifiers());
+        assert!(!layout("%{A:x:}ab%{A}%{A3:y:}cd%{A}").needs_modifiers());
+    }
+}
@@ -1,11 +1,18 @@
 use clap::Parser;
 use status::Bar;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+mod bitmap;
 mod cli;
 mod consts;
 mod error;
+mod font;
+mod icon;
+mod layout;
 mod paint;
 mod parse;
+mod popup;
 mod status;
 
 fn main() {
@@ -15,9 +22,11 @@ fn main() {
     let permaent = config.permaent();
     let (mut state, mut event_queue) = Bar::new(config);
     let data = state.data();
-    let condvar = state.condvar();
+    let waker = state.waker();
+    let input_ended = Arc::new(AtomicBool::new(false));
 
-    let io_thread = std::thread::spawn(move || {
+    let io_thread_ended = input_ended.clone();
+    std::thread::spawn(move || {
         loop {
             let mut input = String::new();
             #[cfg(feature = "logs")]
@@ -47,20 +56,23 @@ fn main() {
             let mut mutex = data.lock().unwrap();
             mutex.0 = input;
             mutex.1 = true;
-            condvar.notify_one();
+            drop(mutex);
+            waker.wake();
         }
         if permaent {
+            // Keep the bar after the input ends
             loop {
-                let mut mutex = data.lock().unwrap();
-                mutex.1 = true;
-                condvar.notify_one();
+                std::thread::park();
             }
         }
+        // The event loop only checks whether this ended once woken up
+        io_thread_ended.store(true, Ordering::Release);
+        waker.wake();
     });
 
     loop {
         event_queue.blocking_dispatch(&mut state).unwrap();
-        if state.req_exit() || io_thread.is_finished() {
+        if state.req_exit() || input_ended.load(Ordering::Acquire) {
             std::process::exit(0);
         }
     }
@@ -1,8 +1,9 @@
+use crate::bitmap::BitmapGlyph;
 use crate::cli::Color;
 use crate::error::Error;
-use ab_glyph::FontArc;
-use ab_glyph::PxScaleFont;
+use crate::font::Face;
 use ab_glyph::ScaleFont;
+use wayland_client::protocol::wl_output::Transform;
 
 pub trait Paintable {
     fn width(&self) -> usize;
@@ -12,6 +13,143 @@ pub trait Paintable {
     fn draw_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), Error> {
         self.set_pixel(x, y, color.blend(&self.get_pixel(x, y)?))
     }
+    /// Fill a rectangle with `color`, replacing the pixels beneath it.
+    ///
+    /// The rectangle is clipped to the paintable.
+    fn fill_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), Error> {
+        for y in y..(y + height).min(self.height()) {
+            for x in x..(x + width).min(self.width()) {
+                self.set_pixel(x, y, color)?;
+            }
+        }
+        Ok(())
+    }
+    /// Blend `color` over a rectangle.
+    ///
+    /// The rectangle is clipped to the paintable.
+    fn blend_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), Error> {
+        for y in y..(y + height).min(self.height()) {
+            for x in x..(x + width).min(self.width()) {
+                self.draw_pixel(x, y, color)?;
+            }
+        }
+        Ok(())
+    }
+    /// Write a row of pixels starting at (`x`, `y`), replacing the pixels beneath it.
+    ///
+    /// Pixels falling outside of the paintable are dropped.
+    fn set_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        if y >= self.height() {
+            return Ok(());
+        }
+        for (x, color) in (x..self.width()).zip(colors) {
+            self.set_pixel(x, y, *color)?;
+        }
+        Ok(())
+    }
+    /// Blend a row of pixels over the paintable, starting at (`x`, `y`).
+    ///
+    /// Pixels falling outside of the paintable are dropped.
+    fn blend_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        if y >= self.height() {
+            return Ok(());
+        }
+        for (x, color) in (x..self.width()).zip(colors) {
+            self.draw_pixel(x, y, *color)?;
+        }
+        Ok(())
+    }
+    /// Draw an antialiased line, 1 pixel wide, between two points.
+    ///
+    /// Pixels falling outside of the paintable are dropped.
+    fn draw_line(&mut self, from: (f32, f32), to: (f32, f32), color: Color) -> Result<(), Error> {
+        // Xiaolin Wu's algorithm
+        let steep = (to.1 - from.1).abs() > (to.0 - from.0).abs();
+        let (mut from, mut to) = if steep {
+            ((from.1, from.0), (to.1, to.0))
+        } else {
+            (from, to)
+        };
+        if from.0 > to.0 {
+            std::mem::swap(&mut from, &mut to);
+        }
+        let gradient = if to.0 - from.0 == 0. {
+            1.
+        } else {
+            (to.1 - from.1) / (to.0 - from.0)
+        };
+        let mut plot = |x: f32, y: f32, coverage: f32| -> Result<(), Error> {
+            let (x, y) = if steep { (y, x) } else { (x, y) };
+            if x < 0. || y < 0. || x >= self.width() as f32 || y >= self.height() as f32 {
+                return Ok(());
+            }
+            self.draw_pixel(
+                x as usize,
+                y as usize,
+                color.with_alpha((coverage * 255.) as u8),
+            )
+        };
+
+        let x_start = from.0.round();
+        let y_start = from.1 + gradient * (x_start - from.0);
+        let gap = 1. - (from.0 + 0.5).fract();
+        plot(x_start, y_start.floor(), (1. - y_start.fract()) * gap)?;
+        plot(x_start, y_start.floor() + 1., y_start.fract() * gap)?;
+
+        let x_end = to.0.round();
+        let y_end = to.1 + gradient * (x_end - to.0);
+        let gap = (to.0 + 0.5).fract();
+        plot(x_end, y_end.floor(), (1. - y_end.fract()) * gap)?;
+        plot(x_end, y_end.floor() + 1., y_end.fract() * gap)?;
+
+        let mut y = y_start + gradient;
+        let mut x = x_start + 1.;
+        while x < x_end {
+            plot(x, y.floor(), 1. - y.fract())?;
+            plot(x, y.floor() + 1., y.fract())?;
+            y += gradient;
+            x += 1.;
+        }
+        Ok(())
+    }
+    /// Blend `color` over a `width` × `height` area at (`x`, `y`), weighted by `coverage`.
+    ///
+    /// `coverage` gets the center of each pixel relative to (`x`, `y`),
+    /// and returns how much of that pixel is covered, between 0 and 1.
+    fn fill_shape(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+        coverage: impl Fn(f32, f32) -> f32,
+    ) -> Result<(), Error> {
+        let mut row = Vec::with_capacity(width);
+        for dy in 0..height {
+            row.clear();
+            row.extend((0..width).map(|dx| {
+                let coverage = coverage(dx as f32 + 0.5, dy as f32 + 0.5).clamp(0., 1.);
+                color.with_alpha((coverage * 255.) as u8)
+            }));
+            self.blend_span(x, y + dy, &row)?;
+        }
+        Ok(())
+    }
     fn slice<'slice>(
         &'slice mut self,
         x: usize,
@@ -30,6 +168,39 @@ pub trait Paintable {
     }
 }
 
+/// Get the coverage of a rectangle with rounded corners, placed at the origin.
+pub fn rounded_rect(width: f32, height: f32, radius: f32) -> impl Fn(f32, f32) -> f32 {
+    let radius = radius.min(width / 2.).min(height / 2.).max(0.);
+    move |x, y| {
+        // Signed distance to the edge, negative inside
+        let qx = (x - width / 2.).abs() - (width / 2. - radius);
+        let qy = (y - height / 2.).abs() - (height / 2. - radius);
+        let distance = qx.max(0.).hypot(qy.max(0.)) + qx.max(qy).min(0.) - radius;
+        (0.5 - distance).clamp(0., 1.)
+    }
+}
+
+/// Get the coverage of a triangle pointing right, placed at the origin.
+pub fn arrow_right(width: f32, height: f32) -> impl Fn(f32, f32) -> f32 {
+    let half = height / 2.;
+    let length = half.hypot(width);
+    move |x, y| {
+        // Distance to the nearer slanted edge, negative inside
+        let y = y.min(height - y);
+        let distance = (half * x - width * y) / length;
+        (0.5 - distance).clamp(0., 1.).min((x + 0.5).clamp(0., 1.))
+    }
+}
+
+/// Get the coverage of a half disc bulging right, placed at the origin.
+pub fn half_circle_right(height: f32) -> impl Fn(f32, f32) -> f32 {
+    let radius = height / 2.;
+    move |x, y| {
+        let distance = x.hypot(y - radius) - radius;
+        (0.5 - distance).clamp(0., 1.)
+    }
+}
+
 pub trait Paint {
     fn paint(&self, canvas: &mut impl Paintable) -> Result<(), Error>;
 }
@@ -60,6 +231,16 @@ impl<'buffer> Canvas<'buffer> {
             .get(y * self.width * 4 + x * 4..y * self.width * 4 + x * 4 + 4)
             .map(|v| v.try_into().ok())?
     }
+
+    /// Get the bytes of `len` pixels in row `y` starting from `x`, clipped to the canvas.
+    fn get_row_mut(&mut self, x: usize, y: usize, len: usize) -> Option<&mut [u8]> {
+        if y >= self.height || x >= self.width {
+            return None;
+        }
+        let end = (x + len).min(self.width);
+        self.buffer
+            .get_mut(y * self.width * 4 + x * 4..y * self.width * 4 + end * 4)
+    }
 }
 
 pub struct PaintableSlice<'parent, P>
@@ -71,6 +252,8 @@ where
     y: usize,
     height: usize,
     width: usize,
+    /// How far the content is shifted to the left; what falls before the slice is clipped.
+    scroll: usize,
 }
 
 impl<'parent, P> PaintableSlice<'parent, P>
@@ -90,8 +273,24 @@ where
             y,
             height,
             width,
+            scroll: 0,
         }
     }
+
+    /// Shift the content `scroll` pixels to the left, clipping it at both edges of this.
+    pub fn scrolled(self, scroll: usize) -> Self {
+        Self { scroll, ..self }
+    }
+
+    /// Map a horizontal run of the content to the parent, clipping it.
+    ///
+    /// Returns the position in the parent, how many pixels were cut at the start
+    /// and how many are left, or `None` if nothing is visible.
+    fn clip(&self, x: usize, width: usize) -> Option<(usize, usize, usize)> {
+        let start = x.max(self.scroll);
+        let end = (x + width).min(self.scroll + self.width);
+        (start < end).then(|| (start - self.scroll + self.x, start - x, end - start))
+    }
 }
 
 impl<P> Paintable for PaintableSlice<'_, P>
@@ -99,21 +298,81 @@ where
     P: Paintable,
 {
     fn set_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), Error> {
-        if y >= self.height || x >= self.width {
+        if y >= self.height || x >= self.width() {
             return Err(Error::PointOutbound);
         }
-        self.parent_canvas.set_pixel(x + self.x, y + self.y, color)
+        match self.clip(x, 1) {
+            Some((x, _, _)) => self.parent_canvas.set_pixel(x, y + self.y, color),
+            None => Ok(()),
+        }
     }
 
     fn get_pixel(&self, x: usize, y: usize) -> Result<Color, Error> {
-        if y >= self.height || x >= self.width {
+        if y >= self.height || x >= self.width() {
             return Err(Error::PointOutbound);
         }
-        self.parent_canvas.get_pixel(x + self.x, y + self.y)
+        let (x, _, _) = self.clip(x, 1).ok_or(Error::PointOutbound)?;
+        self.parent_canvas.get_pixel(x, y + self.y)
+    }
+
+    fn draw_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), Error> {
+        if y >= self.height || x >= self.width() {
+            return Err(Error::PointOutbound);
+        }
+        match self.clip(x, 1) {
+            Some((x, _, _)) => self.parent_canvas.draw_pixel(x, y + self.y, color),
+            None => Ok(()),
+        }
+    }
+
+    fn fill_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), Error> {
+        let Some((x, _, width)) = self.clip(x, width).filter(|_| y < self.height) else {
+            return Ok(());
+        };
+        self.parent_canvas
+            .fill_rect(x, y + self.y, width, height.min(self.height - y), color)
+    }
+
+    fn blend_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), Error> {
+        let Some((x, _, width)) = self.clip(x, width).filter(|_| y < self.height) else {
+            return Ok(());
+        };
+        self.parent_canvas
+            .blend_rect(x, y + self.y, width, height.min(self.height - y), color)
+    }
+
+    fn set_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        let Some((x, skip, len)) = self.clip(x, colors.len()).filter(|_| y < self.height) else {
+            return Ok(());
+        };
+        self.parent_canvas
+            .set_span(x, y + self.y, &colors[skip..skip + len])
+    }
+
+    fn blend_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        let Some((x, skip, len)) = self.clip(x, colors.len()).filter(|_| y < self.height) else {
+            return Ok(());
+        };
+        self.parent_canvas
+            .blend_span(x, y + self.y, &colors[skip..skip + len])
     }
 
     fn width(&self) -> usize {
-        self.width
+        self.scroll + self.width
     }
 
     fn height(&self) -> usize {
@@ -123,7 +382,7 @@ where
 
 impl Paintable for Canvas<'_> {
     fn set_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), Error> {
-        if self.height < y || self.width < x {
+        if y >= self.height || x >= self.width {
             return Err(Error::PointOutbound);
         }
         *self.get_buffer_mut(x, y).ok_or(Error::PointOutbound)? = (&color).into();
@@ -131,9 +390,76 @@ impl Paintable for Canvas<'_> {
     }
 
     fn get_pixel(&self, x: usize, y: usize) -> Result<Color, Error> {
+        if y >= self.height || x >= self.width {
+            return Err(Error::PointOutbound);
+        }
         Ok(self.get_buffer(x, y).ok_or(Error::PointOutbound)?.into())
     }
 
+    fn fill_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), Error> {
+        let bytes: [u8; 4] = (&color).into();
+        for y in y..(y + height).min(self.height) {
+            if let Some(row) = self.get_row_mut(x, y, width) {
+                row.chunks_exact_mut(4)
+                    .for_each(|pixel| pixel.copy_from_slice(&bytes));
+            }
+        }
+        Ok(())
+    }
+
+    fn blend_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), Error> {
+        match color.alpha() {
+            0 => return Ok(()),
+            255 => return self.fill_rect(x, y, width, height, color),
+            _ => {}
+        }
+        for y in y..(y + height).min(self.height) {
+            if let Some(row) = self.get_row_mut(x, y, width) {
+                row.chunks_exact_mut(4).for_each(|pixel| {
+                    let pixel: &mut [u8; 4] = pixel.try_into().unwrap();
+                    *pixel = (&color.blend(&(&*pixel).into())).into();
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn set_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        if let Some(row) = self.get_row_mut(x, y, colors.len()) {
+            row.chunks_exact_mut(4)
+                .zip(colors)
+                .for_each(|(pixel, color)| pixel.copy_from_slice(&<[u8; 4]>::from(color)));
+        }
+        Ok(())
+    }
+
+    fn blend_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        if let Some(row) = self.get_row_mut(x, y, colors.len()) {
+            row.chunks_exact_mut(4)
+                .zip(colors)
+                .filter(|(_, color)| color.alpha() != 0)
+                .for_each(|(pixel, color)| {
+                    let pixel: &mut [u8; 4] = pixel.try_into().unwrap();
+                    *pixel = (&color.blend(&(&*pixel).into())).into();
+                });
+        }
+        Ok(())
+    }
+
     fn width(&self) -> usize {
         self.width
     }
@@ -147,58 +473,97 @@ impl Paintable for Canvas<'_> {
 pub struct Text {
     //content: String,
     content: String,
-    fonts: Vec<PxScaleFont<FontArc>>,
+    fonts: Vec<Face>,
     fg_color: Color,
-    bg_color: Color,
 }
 
-impl<'font> Text {
+/// Get the ascent and the descent shared by `fonts`, which sit on the same baseline.
+pub fn line_metrics(fonts: &[Face]) -> (f32, f32) {
+    fonts.iter().fold((0f32, 0f32), |(ascent, descent), v| {
+        (ascent.max(v.ascent()), descent.min(v.descent()))
+    })
+}
+
+impl Text {
     /// Get right font for a character, seeking in all fonts registred in the `fonts` vec.
     ///
     /// The last font was returned if there're no suitable font.
-    fn get_font(&self, ch: char) -> &PxScaleFont<FontArc> {
+    fn get_font(&self, ch: char) -> &Face {
         for i in &self.fonts {
-            let glyph_id = i.glyph_id(ch);
-            if glyph_id.0 == 0 {
+            if !i.has_glyph(ch) {
                 continue;
             }
             return i;
         }
-        return self.fonts.last().unwrap(); // Notdef
+        self.fonts.last().unwrap() // Notdef
     }
 
-    pub fn new(
-        content: String,
-        fonts: Vec<PxScaleFont<FontArc>>,
-        fg_color: Color,
-        bg_color: Color,
-    ) -> Self {
+    pub fn new(content: String, fonts: Vec<Face>, fg_color: Color) -> Self {
         Self {
             content,
             fonts,
             fg_color,
-            bg_color,
         }
     }
 
     pub fn get_region(&self) -> (f32, f32) {
-        let mut cursor = ab_glyph::point(0., self.fonts.first().unwrap().ascent());
+        let (ascent, descent) = line_metrics(&self.fonts);
+        let mut cursor = ab_glyph::point(0., ascent);
         for i in self.content.chars() {
-            let font = self.get_font(i);
-            let glyph_id = font.glyph_id(i);
-            cursor.x += font.h_advance(glyph_id);
+            cursor.x += self.get_font(i).advance(i);
         }
-        (cursor.x, self.fonts.first().unwrap().height())
+        (cursor.x, ascent - descent)
+    }
+
+    /// Paint a bitmap glyph with its pen position at `cursor`, without antialiasing.
+    fn paint_bitmap(
+        &self,
+        canvas: &mut impl Paintable,
+        glyph: &BitmapGlyph,
+        cursor: ab_glyph::Point,
+    ) -> Result<(), Error> {
+        let left = cursor.x.round() as i32 + glyph.x_offset();
+        let top = cursor.y.round() as i32 + glyph.top();
+        let transparent = self.fg_color.with_alpha(0);
+        for y in 0..glyph.height() {
+            let row = (0..glyph.width())
+                .map(|x| match glyph.pixel(x, y) {
+                    true => self.fg_color,
+                    false => transparent,
+                })
+                .collect::<Vec<_>>();
+            // Glyphs may hang over the left or top edge; skip those pixels
+            let (Ok(row_y), Ok(skip_x)) = (
+                usize::try_from(top + y as i32),
+                usize::try_from((-left).max(0)),
+            ) else {
+                continue;
+            };
+            canvas.blend_span(
+                (left + skip_x as i32) as usize,
+                row_y,
+                row.get(skip_x..).unwrap_or_default(),
+            )?;
+        }
+        Ok(())
     }
 }
 
 impl Paint for Text {
     fn paint(&self, canvas: &mut impl Paintable) -> Result<(), Error> {
-        let mut cursor = ab_glyph::point(0., self.fonts.first().unwrap().ascent());
+        let mut cursor = ab_glyph::point(0., line_metrics(&self.fonts).0);
         for i in self.content.chars() {
-            let font = self.get_font(i);
+            let (font, glyph_id) = match self.get_font(i) {
+                Face::Outline(face) => (face.font(), face.glyph_id(i)),
+                Face::Bitmap(font) => {
+                    if let Some(glyph) = font.glyph(i) {
+                        self.paint_bitmap(canvas, glyph, cursor)?;
+                        cursor.x += glyph.advance() as f32;
+                    }
+                    continue;
+                }
+            };
             let scale: ab_glyph::PxScale = font.scale();
-            let glyph_id = font.glyph_id(i);
             let glyph = glyph_id.with_scale_and_position(scale, cursor);
             let outline = font.outline_glyph(glyph).unwrap_or_else(
                 || {
@@ -207,20 +572,183 @@ impl Paint for Text {
                 }, // There MUST be at least 1 glyphs
             );
             if i != ' ' {
+                let bounds = outline.px_bounds();
+                let width = bounds.width() as usize;
+                let mut pixels =
+                    vec![Color::new(0, 0, 0, 0); width * bounds.height() as usize];
                 outline.draw(|x, y, v| {
-                    canvas
-                        .draw_pixel(
-                            (x as f32 + outline.px_bounds().min.x) as usize,
-                            (y as f32 + outline.px_bounds().min.y) as usize,
-                            self.fg_color
-                                .with_alpha((v * 256.) as u8)
-                                .blend(&self.bg_color),
-                        )
-                        .unwrap()
+                    if let Some(pixel) = pixels.get_mut(y as usize * width + x as usize) {
+                        *pixel = self.fg_color.with_alpha((v * 256.) as u8);
+                    }
                 });
+                // Glyphs may hang over the left or top edge; skip those pixels
+                let skip_x = (-bounds.min.x).max(0.) as usize;
+                let skip_y = (-bounds.min.y).max(0.) as usize;
+                for (y, row) in pixels.chunks_exact(width.max(1)).enumerate().skip(skip_y) {
+                    canvas.blend_span(
+                        (bounds.min.x + skip_x as f32) as usize,
+                        (y as f32 + bounds.min.y) as usize,
+                        row.get(skip_x..).unwrap_or_default(),
+                    )?;
+                }
             }
             cursor.x += font.h_advance(glyph_id);
         }
         Ok(())
     }
 }
+
+/// An owned block of pixels, e.g. a decoded image.
+#[derive(Debug, Clone)]
+pub struct Pixmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Pixmap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new(0, 0, 0, 0); width * height],
+        }
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Result<Self, Error> {
+        if pixels.len() != width * height {
+            return Err(Error::InvalidImage);
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Resample this to the given size, averaging all source pixels covering each new pixel.
+    pub fn scaled(&self, width: usize, height: usize) -> Self {
+        let mut retval = Self::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return retval;
+        }
+        for y in 0..height {
+            let y_start = y * self.height / height;
+            let y_end = ((y + 1) * self.height / height).max(y_start + 1);
+            for x in 0..width {
+                let x_start = x * self.width / width;
+                let x_end = ((x + 1) * self.width / width).max(x_start + 1);
+                // Average with premultiplied alpha to keep transparent pixels from bleeding
+                let (mut r, mut g, mut b, mut a, mut n) = (0u32, 0u32, 0u32, 0u32, 0u32);
+                for row in self.pixels[y_start * self.width..y_end * self.width]
+                    .chunks_exact(self.width)
+                {
+                    for pixel in &row[x_start..x_end] {
+                        let [pb, pg, pr, pa] = <[u8; 4]>::from(pixel);
+                        let pa: u32 = pa.into();
+                        r += u32::from(pr) * pa;
+                        g += u32::from(pg) * pa;
+                        b += u32::from(pb) * pa;
+                        a += pa;
+                        n += 1;
+                    }
+                }
+                if let (Some(r), Some(g), Some(b)) =
+                    (r.checked_div(a), g.checked_div(a), b.checked_div(a))
+                {
+                    retval.pixels[y * width + x] =
+                        Color::new(r as u8, g as u8, b as u8, (a / n) as u8);
+                }
+            }
+        }
+        retval
+    }
+
+    /// Copy this onto `canvas` at (`x`, `y`), replacing the pixels beneath it.
+    pub fn blit(&self, canvas: &mut impl Paintable, x: usize, y: usize) -> Result<(), Error> {
+        if self.width == 0 {
+            return Ok(());
+        }
+        for (dy, row) in self.pixels.chunks_exact(self.width).enumerate() {
+            canvas.set_span(x, y + dy, row)?;
+        }
+        Ok(())
+    }
+
+    /// Copy this onto `canvas` transformed like a buffer with `transform` is expected to be.
+    ///
+    /// `canvas` has the width and the height swapped for quarter turns.
+    pub fn blit_transformed(
+        &self,
+        canvas: &mut impl Paintable,
+        transform: Transform,
+    ) -> Result<(), Error> {
+        let (width, height) = (self.width, self.height);
+        if width == 0 {
+            return Ok(());
+        }
+        for (y, row) in self.pixels.chunks_exact(width).enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let (flipped_x, flipped_y) = (width - 1 - x, height - 1 - y);
+                let (x, y) = match transform {
+                    Transform::_90 => (y, flipped_x),
+                    Transform::_180 => (flipped_x, flipped_y),
+                    Transform::_270 => (flipped_y, x),
+                    Transform::Flipped => (flipped_x, y),
+                    Transform::Flipped90 => (y, x),
+                    Transform::Flipped180 => (x, flipped_y),
+                    Transform::Flipped270 => (flipped_y, flipped_x),
+                    _ => (x, y),
+                };
+                canvas.set_pixel(x, y, *color)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Paintable for Pixmap {
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), Error> {
+        if y >= self.height || x >= self.width {
+            return Err(Error::PointOutbound);
+        }
+        self.pixels[y * self.width + x] = color;
+        Ok(())
+    }
+
+    fn get_pixel(&self, x: usize, y: usize) -> Result<Color, Error> {
+        if y >= self.height || x >= self.width {
+            return Err(Error::PointOutbound);
+        }
+        Ok(self.pixels[y * self.width + x])
+    }
+
+    fn set_span(&mut self, x: usize, y: usize, colors: &[Color]) -> Result<(), Error> {
+        if y >= self.height || x >= self.width {
+            return Ok(());
+        }
+        let len = colors.len().min(self.width - x);
+        self.pixels[y * self.width + x..][..len].copy_from_slice(&colors[..len]);
+        Ok(())
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl Paint for Pixmap {
+    fn paint(&self, canvas: &mut impl Paintable) -> Result<(), Error> {
+        if self.width == 0 {
+            return Ok(());
+        }
+        for (y, row) in self.pixels.chunks_exact(self.width).enumerate() {
+            canvas.blend_span(0, y, row)?;
+        }
+        Ok(())
+    }
+}
@@ -9,27 +9,94 @@ impl StyledString {
     }
 }
 
+/// Modifier keys held on the keyboard.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    /// Also known as super or windows.
+    pub logo: bool,
+}
+
+/// What runs an action.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Trigger {
+    /// A button pressed with exactly these modifiers.
+    Button(u8, Modifiers),
+    /// The pointer entering the area.
+    Enter,
+    /// The pointer leaving the area.
+    Leave,
+    /// The pointer resting on the area, which shows the command as markup in a tooltip.
+    Tooltip,
+    /// A button opening a menu of the items in the command, see [`menu_items`].
+    Menu(u8, Modifiers),
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Action {
-    button: u8,
+    trigger: Trigger,
     cmd: String,
 }
 
 impl Action {
-    pub fn into_tuple(self) -> (u8, String) {
-        (self.button, self.cmd)
+    pub fn into_tuple(self) -> (Trigger, String) {
+        (self.trigger, self.cmd)
     }
 }
 
+/// An entry of a menu.
+#[derive(PartialEq, Debug)]
+pub struct MenuItem {
+    /// Markup shown in the menu.
+    pub label: String,
+    pub cmd: String,
+}
+
+/// Get the items of a menu written as `label=command;label=command`.
+///
+/// Items without a label show their command.
+pub fn menu_items(spec: &str) -> Vec<MenuItem> {
+    spec.split(';')
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| match v.split_once('=') {
+            Some((label, cmd)) => MenuItem {
+                label: label.into(),
+                cmd: cmd.into(),
+            },
+            None => MenuItem {
+                label: v.into(),
+                cmd: v.into(),
+            },
+        })
+        .collect()
+}
+
 #[derive(PartialEq, Debug)]
 pub enum StyledStringPart {
     String(String),
     Style(Style),
+    /// Colors of the text while the pointer is over the current action area.
+    HoverStyle(Style),
     Action(Action),
     ActionEnd,
     Swap,
     Align(Align),
     Offset(usize),
+    Image(std::path::PathBuf),
+    Progress(Progress),
+    Rectangle(Rectangle),
+    Separator(Separator),
+    Graph(Graph),
+    BoxStart(BoxStyle),
+    BoxEnd,
+    Powerline(Powerline),
+    /// Start of a segment at most this wide, which scrolls when its content is wider.
+    MarqueeStart(usize),
+    MarqueeEnd,
+    FieldStart(Field),
+    FieldEnd,
     Attribute {
         attribute: Attribute,
         action: AttributeAction,
@@ -40,6 +107,8 @@ pub enum StyledStringPart {
 pub enum Attribute {
     Overline,
     Underline,
+    /// Text that may be cut with an ellipsis when the bar overflows.
+    Ellipsis,
 }
 
 #[derive(PartialEq, Debug)]
@@ -49,7 +118,7 @@ pub enum AttributeAction {
     Toggle,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Align {
     Left,
     Center,
@@ -93,21 +162,202 @@ impl Style {
     }
 }
 
+/// A horizontal gauge, filled according to `value / max`.
+#[derive(PartialEq, Debug)]
+pub struct Progress {
+    width: usize,
+    value: f32,
+    max: f32,
+    foreground_color: Color,
+    background_color: Color,
+}
+
+impl Progress {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the filled part, between 0 and 1.
+    pub fn ratio(&self) -> f32 {
+        if self.max > 0. {
+            (self.value / self.max).clamp(0., 1.)
+        } else {
+            0.
+        }
+    }
+
+    pub fn foreground_color(&self) -> Color {
+        self.foreground_color
+    }
+
+    pub fn background_color(&self) -> Color {
+        self.background_color
+    }
+}
+
+/// A solid rectangle; the height defaults to the line height.
+#[derive(PartialEq, Debug)]
+pub struct Rectangle {
+    width: usize,
+    height: Option<usize>,
+    color: Color,
+}
+
+impl Rectangle {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> Option<usize> {
+        self.height
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+}
+
+/// A vertical line spanning the line height.
+#[derive(PartialEq, Debug)]
+pub struct Separator {
+    thickness: usize,
+    color: Color,
+}
+
+impl Separator {
+    pub fn thickness(&self) -> usize {
+        self.thickness
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GraphStyle {
+    Line,
+    Bars,
+}
+
+/// A small history graph.
+///
+/// Values of a named graph are appended to the series of that name,
+/// instead of replacing it.
+#[derive(PartialEq, Debug)]
+pub struct Graph {
+    style: GraphStyle,
+    name: Option<String>,
+    width: usize,
+    values: Vec<f32>,
+}
+
+impl Graph {
+    pub fn style(&self) -> GraphStyle {
+        self.style
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn into_values(self) -> Vec<f32> {
+        self.values
+    }
+}
+
+/// A rounded box drawn behind a segment.
+#[derive(PartialEq, Debug)]
+pub struct BoxStyle {
+    radius: usize,
+    padding: usize,
+    background_color: Color,
+    border: Option<(usize, crate::cli::Color)>,
+}
+
+impl BoxStyle {
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
+    pub fn padding(&self) -> usize {
+        self.padding
+    }
+
+    pub fn background_color(&self) -> Color {
+        self.background_color
+    }
+
+    /// Get the width and the color of the border, if any.
+    pub fn border(&self) -> Option<(usize, crate::cli::Color)> {
+        self.border
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FieldWidth {
+    Pixels(usize),
+    /// A number of digits of the primary font.
+    Chars(usize),
+}
+
+/// A box of fixed width, with its content aligned inside.
+#[derive(PartialEq, Debug)]
+pub struct Field {
+    width: FieldWidth,
+    align: Align,
+}
+
+impl Field {
+    pub fn width(&self) -> FieldWidth {
+        self.width
+    }
+
+    pub fn align(&self) -> Align {
+        self.align
+    }
+}
+
+/// A separator spanning the whole bar height, pointing left or right.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Powerline {
+    ArrowLeft,
+    ArrowRight,
+    RoundLeft,
+    RoundRight,
+}
+
 peg::parser! {
     grammar styled_string() for str {
         rule color() -> crate::cli::Color
-        = n:['0'..='9'|'A'..='F'|'a'..='f']*<3,8> {?
+        = "#"? n:['0'..='9'|'A'..='F'|'a'..='f']*<3,8> {?
             format!("#{}", n.iter().collect::<String>()).parse().map_err(|_| "Invalid string")
         }
         rule positive_number() -> usize
             = n:['0'..='9']+ {?
                 n.iter().collect::<String>().parse().map_err(|_| "Invalid number")
             }
+        rule decimal() -> f32
+            = n:$(['0'..='9']+ ("." ['0'..='9']+)?) {?
+                n.parse().map_err(|_| "Invalid number")
+            }
+        rule optional_color() -> Color
+            = ":" c:color() {Color::New(c)}
+            / "" {Color::Now}
         rule formatting_block() -> Style
             = "%{B" c:color() "}" {Style{foreground_color:Color::Now, background_color:Color::New(c)}}
             / "%{F" c:color() "}" {Style{foreground_color:Color::New(c), background_color:Color::Now}}
             / "%{B-}" {Style{foreground_color:Color::Now, background_color:Color::Default}}
             / "%{F-}" {Style{background_color:Color::Now, foreground_color:Color::Default}}
+        rule hover_block() -> Style
+            = "%{HB" c:color() "}" {Style{foreground_color:Color::Now, background_color:Color::New(c)}}
+            / "%{HF" c:color() "}" {Style{foreground_color:Color::New(c), background_color:Color::Now}}
+            / "%{HB-}" {Style{foreground_color:Color::Now, background_color:Color::Default}}
+            / "%{HF-}" {Style{background_color:Color::Now, foreground_color:Color::Default}}
         rule attribute_action() -> AttributeAction
             = "+" {AttributeAction::On}
             / "-" {AttributeAction::Off}
@@ -115,11 +365,33 @@ peg::parser! {
         rule attribute() -> Attribute
             = "o" {Attribute::Overline}
             / "u" {Attribute::Underline}
+            / "e" {Attribute::Ellipsis}
+        rule modifiers() -> Modifiers
+            = m:$(['S' | 'C' | 'A' | 'W']*) {
+                Modifiers {
+                    shift: m.contains('S'),
+                    ctrl: m.contains('C'),
+                    alt: m.contains('A'),
+                    logo: m.contains('W'),
+                }
+            }
+        rule button() -> u8
+            = b:['1'..='9'] {b as u8 - b'0'}
+        // Runs up to the closing ":}", with `\:` for a colon that would close it
+        rule payload() -> String
+            = c:(("\\" c:[':'] {c}) / !":}" c:[_] {c})+ {c.into_iter().collect()}
         rule action() -> StyledStringPart
-            = "%{A" button:(['1'..='5']?) ":" cmd:([^':']+) ":}" {?
-                Ok(StyledStringPart::Action(Action{
-                    button:button.unwrap_or('1') as u8 - '0' as u8, cmd:cmd.iter().collect()
-                }))
+            = "%{A" trigger:(
+                    m:modifiers() b:button() {Trigger::Button(b, m)}
+                    / ">" {Trigger::Enter}
+                    / "<" {Trigger::Leave}
+                    / "?" {Trigger::Tooltip}
+                    / "m" m:modifiers() b:button()? {Trigger::Menu(b.unwrap_or(1), m)}
+                )? ":" cmd:payload() ":}" {
+                StyledStringPart::Action(Action{
+                    trigger:trigger.unwrap_or(Trigger::Button(1, Modifiers::default())),
+                    cmd
+                })
             }
             / "%{A}" {StyledStringPart::ActionEnd}
             / "%{R}" {StyledStringPart::Swap}
@@ -129,11 +401,53 @@ peg::parser! {
             / "%{O" number:positive_number() "}" {
                 StyledStringPart::Offset(number)
             }
+            / "%{P" width:positive_number() ":" value:decimal() "/" max:decimal()
+                foreground_color:optional_color() background_color:optional_color() "}" {
+                StyledStringPart::Progress(Progress {
+                    width, value, max, foreground_color, background_color
+                })
+            }
+            / "%{X" width:positive_number() height:("x" h:positive_number() {h})?
+                color:optional_color() "}" {
+                StyledStringPart::Rectangle(Rectangle { width, height, color })
+            }
+            / "%{|" thickness:positive_number()? color:optional_color() "}" {
+                StyledStringPart::Separator(Separator { thickness: thickness.unwrap_or(1), color })
+            }
+            / "%{G" style:("l" {GraphStyle::Line} / "b" {GraphStyle::Bars})?
+                name:("@" n:$([^':'|'}']+) {n.to_owned()})?
+                ":" width:positive_number() ":" values:(decimal() ** ",") "}" {
+                StyledStringPart::Graph(Graph {
+                    style: style.unwrap_or(GraphStyle::Line), name, width, values
+                })
+            }
+            / "%{[" radius:positive_number() ":" padding:positive_number()
+                background_color:optional_color()
+                border:(":" w:positive_number() ":" c:color() {(w, c)})? "}" {
+                StyledStringPart::BoxStart(BoxStyle { radius, padding, background_color, border })
+            }
+            / "%{]}" {StyledStringPart::BoxEnd}
+            / "%{<}" {StyledStringPart::Powerline(Powerline::ArrowLeft)}
+            / "%{>}" {StyledStringPart::Powerline(Powerline::ArrowRight)}
+            / "%{(}" {StyledStringPart::Powerline(Powerline::RoundLeft)}
+            / "%{)}" {StyledStringPart::Powerline(Powerline::RoundRight)}
+            / "%{M" width:positive_number() "}" {StyledStringPart::MarqueeStart(width)}
+            / "%{M}" {StyledStringPart::MarqueeEnd}
+            / "%{W" n:positive_number()
+                width:("ch" {FieldWidth::Chars(n)} / "px"? {FieldWidth::Pixels(n)})
+                align:(":" a:("l" {Align::Left} / "c" {Align::Center} / "r" {Align::Right}) {a})? "}" {
+                StyledStringPart::FieldStart(Field { width, align: align.unwrap_or(Align::Left) })
+            }
+            / "%{W}" {StyledStringPart::FieldEnd}
+            / "%{I" path:$([^'}']+) "}" {
+                StyledStringPart::Image(path.into())
+            }
             / "%{" action:attribute_action() attribute:attribute() "}" {
                 StyledStringPart::Attribute { attribute, action }
             }
         rule part() -> StyledStringPart
             = f:formatting_block() {StyledStringPart::Style(f)}
+            / h:hover_block() {StyledStringPart::HoverStyle(h)}
             / a:action() {a}
             / s:([^'%']+) {StyledStringPart::String(s.iter().collect())}
         pub rule string() -> StyledString
@@ -153,3 +467,306 @@ impl std::str::FromStr for StyledString {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Vec<StyledStringPart> {
+        s.parse::<StyledString>().unwrap().into_content()
+    }
+
+    fn action(trigger: Trigger, cmd: &str) -> StyledStringPart {
+        StyledStringPart::Action(Action {
+            trigger,
+            cmd: cmd.into(),
+        })
+    }
+
+    fn color(s: &str) -> crate::cli::Color {
+        s.parse().unwrap()
+    }
+
+    fn fails(s: &str) -> bool {
+        s.parse::<StyledString>().is_err()
+    }
+
+    const LEFT: Trigger = Trigger::Button(
+        1,
+        Modifiers {
+            shift: false,
+            ctrl: false,
+            alt: false,
+            logo: false,
+        },
+    );
+
+    #[test]
+    fn action_commands_run_to_the_closing_colon() {
+        assert_eq!(
+            parse("%{A:notify-send a:b:}x%{A}"),
+            [
+                action(LEFT, "notify-send a:b"),
+                StyledStringPart::String("x".into()),
+                StyledStringPart::ActionEnd,
+            ]
+        );
+        assert_eq!(parse("%{A:echo 50%:}"), [action(LEFT, "echo 50%")]);
+        assert_eq!(parse(r"%{A:a\:}b:}"), [action(LEFT, "a:}b")]);
+        assert!("%{A::}".parse::<StyledString>().is_err());
+    }
+
+    #[test]
+    fn action_triggers() {
+        let shift_ctrl = Modifiers {
+            shift: true,
+            ctrl: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse("%{ASC3:x:}"),
+            [action(Trigger::Button(3, shift_ctrl), "x")]
+        );
+        assert_eq!(parse("%{A>:x:}"), [action(Trigger::Enter, "x")]);
+        assert_eq!(parse("%{A<:x:}"), [action(Trigger::Leave, "x")]);
+        assert_eq!(
+            parse("%{A?:Time: 12:00:}"),
+            [action(Trigger::Tooltip, "Time: 12:00")]
+        );
+        assert_eq!(
+            parse("%{Am:x:}"),
+            [action(Trigger::Menu(1, Modifiers::default()), "x")]
+        );
+        assert_eq!(
+            parse("%{AmW3:x:}"),
+            [action(
+                Trigger::Menu(
+                    3,
+                    Modifiers {
+                        logo: true,
+                        ..Default::default()
+                    }
+                ),
+                "x"
+            )]
+        );
+    }
+
+    #[test]
+    fn menu_items_may_contain_colons() {
+        let [StyledStringPart::Action(action)] = &parse("%{Am:Copy=wl-copy a:b;Time 12:30:}")[..]
+        else {
+            panic!("Expected a single action");
+        };
+        assert_eq!(
+            menu_items(&action.cmd),
+            [
+                MenuItem {
+                    label: "Copy".into(),
+                    cmd: "wl-copy a:b".into(),
+                },
+                MenuItem {
+                    label: "Time 12:30".into(),
+                    cmd: "Time 12:30".into(),
+                },
+            ]
+        );
+        assert_eq!(menu_items(" ; "), []);
+    }
+
+    #[test]
+    fn progress_bars() {
+        assert_eq!(
+            parse("%{P40:3/4}"),
+            [StyledStringPart::Progress(Progress {
+                width: 40,
+                value: 3.,
+                max: 4.,
+                foreground_color: Color::Now,
+                background_color: Color::Now,
+            })]
+        );
+        assert_eq!(
+            parse("%{P40:0.5/1:#f00:#0f0}"),
+            [StyledStringPart::Progress(Progress {
+                width: 40,
+                value: 0.5,
+                max: 1.,
+                foreground_color: Color::New(color("#f00")),
+                background_color: Color::New(color("#0f0")),
+            })]
+        );
+        assert!(fails("%{P40:3}"));
+    }
+
+    #[test]
+    fn rectangles() {
+        assert_eq!(
+            parse("%{X10}"),
+            [StyledStringPart::Rectangle(Rectangle {
+                width: 10,
+                height: None,
+                color: Color::Now,
+            })]
+        );
+        assert_eq!(
+            parse("%{X10x4:#f00}"),
+            [StyledStringPart::Rectangle(Rectangle {
+                width: 10,
+                height: Some(4),
+                color: Color::New(color("#f00")),
+            })]
+        );
+        assert!(fails("%{Xx4}"));
+    }
+
+    #[test]
+    fn separators() {
+        assert_eq!(
+            parse("%{|}"),
+            [StyledStringPart::Separator(Separator {
+                thickness: 1,
+                color: Color::Now,
+            })]
+        );
+        assert_eq!(
+            parse("%{|3:#f00}"),
+            [StyledStringPart::Separator(Separator {
+                thickness: 3,
+                color: Color::New(color("#f00")),
+            })]
+        );
+        assert_eq!(
+            parse("%{|:#f00}"),
+            [StyledStringPart::Separator(Separator {
+                thickness: 1,
+                color: Color::New(color("#f00")),
+            })]
+        );
+        assert!(fails("%{|a}"));
+    }
+
+    #[test]
+    fn graphs() {
+        assert_eq!(
+            parse("%{G:20:1,2.5,3}"),
+            [StyledStringPart::Graph(Graph {
+                style: GraphStyle::Line,
+                name: None,
+                width: 20,
+                values: vec![1., 2.5, 3.],
+            })]
+        );
+        assert_eq!(
+            parse("%{Gb@cpu load:20:5}"),
+            [StyledStringPart::Graph(Graph {
+                style: GraphStyle::Bars,
+                name: Some("cpu load".into()),
+                width: 20,
+                values: vec![5.],
+            })]
+        );
+        assert_eq!(
+            parse("%{Gl:20:}"),
+            [StyledStringPart::Graph(Graph {
+                style: GraphStyle::Line,
+                name: None,
+                width: 20,
+                values: vec![],
+            })]
+        );
+        assert!(fails("%{G@:20:1}"));
+        assert!(fails("%{G20:1}"));
+    }
+
+    #[test]
+    fn boxes() {
+        assert_eq!(
+            parse("%{[4:2}x%{]}"),
+            [
+                StyledStringPart::BoxStart(BoxStyle {
+                    radius: 4,
+                    padding: 2,
+                    background_color: Color::Now,
+                    border: None,
+                }),
+                StyledStringPart::String("x".into()),
+                StyledStringPart::BoxEnd,
+            ]
+        );
+        assert_eq!(
+            parse("%{[4:2:#f00:1:#0f0}"),
+            [StyledStringPart::BoxStart(BoxStyle {
+                radius: 4,
+                padding: 2,
+                background_color: Color::New(color("#f00")),
+                border: Some((1, color("#0f0"))),
+            })]
+        );
+        // The border alone, without a background
+        assert_eq!(
+            parse("%{[4:2:1:#0f0}"),
+            [StyledStringPart::BoxStart(BoxStyle {
+                radius: 4,
+                padding: 2,
+                background_color: Color::Now,
+                border: Some((1, color("#0f0"))),
+            })]
+        );
+        assert!(fails("%{[4}"));
+    }
+
+    #[test]
+    fn powerlines() {
+        assert_eq!(
+            parse("%{<}%{>}%{(}%{)}"),
+            [
+                StyledStringPart::Powerline(Powerline::ArrowLeft),
+                StyledStringPart::Powerline(Powerline::ArrowRight),
+                StyledStringPart::Powerline(Powerline::RoundLeft),
+                StyledStringPart::Powerline(Powerline::RoundRight),
+            ]
+        );
+        assert!(fails("%{<>}"));
+    }
+
+    #[test]
+    fn marquees() {
+        assert_eq!(
+            parse("%{M80}x%{M}"),
+            [
+                StyledStringPart::MarqueeStart(80),
+                StyledStringPart::String("x".into()),
+                StyledStringPart::MarqueeEnd,
+            ]
+        );
+        assert!(fails("%{Mx}"));
+    }
+
+    #[test]
+    fn fields() {
+        let field = |width, align| StyledStringPart::FieldStart(Field { width, align });
+        assert_eq!(
+            parse("%{W50}x%{W}"),
+            [
+                field(FieldWidth::Pixels(50), Align::Left),
+                StyledStringPart::String("x".into()),
+                StyledStringPart::FieldEnd,
+            ]
+        );
+        assert_eq!(
+            parse("%{W50px:r}"),
+            [field(FieldWidth::Pixels(50), Align::Right)]
+        );
+        assert_eq!(
+            parse("%{W8ch:c}"),
+            [field(FieldWidth::Chars(8), Align::Center)]
+        );
+        assert_eq!(
+            parse("%{W8ch:l}"),
+            [field(FieldWidth::Chars(8), Align::Left)]
+        );
+        assert!(fails("%{W8em}"));
+        assert!(fails("%{W50:x}"));
+    }
+}
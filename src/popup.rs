@@ -0,0 +1,238 @@
+use crate::cli;
+use crate::error::Error;
+use crate::layout::Layout;
+use crate::paint::Paintable;
+use crate::status::Bar;
+use sctk::compositor::CompositorState;
+use sctk::error::GlobalError;
+use sctk::shell::wlr_layer::LayerSurface;
+use sctk::shell::xdg::popup::Popup;
+use sctk::shell::xdg::{XdgPositioner, XdgShell};
+use sctk::shm::slot::{Buffer, SlotPool};
+use smithay_client_toolkit as sctk;
+use std::time::Duration;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::protocol::wl_shm;
+use wayland_client::QueueHandle;
+use wayland_protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use wayland_protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
+use wayland_protocols::xdg::shell::client::xdg_positioner::{
+    Anchor, ConstraintAdjustment, Gravity,
+};
+
+/// A line of a popup.
+pub struct Row {
+    pub layout: Layout,
+    /// How the row looks while selected, if it can be.
+    pub selected: Option<Layout>,
+}
+
+/// A popup under an area of the bar, showing rows of markup laid out like the bar.
+pub struct MarkupPopup {
+    popup: Popup,
+    viewport: Option<WpViewport>,
+    rows: Vec<Row>,
+    selected: Option<usize>,
+    /// Device pixels per logical one, in 120ths.
+    scale: u32,
+    /// Whether the compositor configured the popup, which can be painted from then on.
+    configured: bool,
+    buffer: Option<Buffer>,
+}
+
+impl MarkupPopup {
+    /// Open a popup showing `rows` under the logical range `start..end` of the bar.
+    ///
+    /// The popup grabs the seat of `grab` when given, with the serial of the input opening it.
+    /// Nothing is shown until the compositor configures the popup and [`Self::configure`] is
+    /// called.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        qh: &QueueHandle<Bar>,
+        compositor: &CompositorState,
+        xdg_shell: &XdgShell,
+        viewporter: Option<&WpViewporter>,
+        layer: &LayerSurface,
+        (start, end): (i32, i32),
+        bar_height: u32,
+        rows: Vec<Row>,
+        scale: u32,
+        grab: Option<&(WlSeat, u32)>,
+    ) -> Result<Self, GlobalError> {
+        let (width, height) = logical_size(device_size(&rows), scale);
+        let positioner = XdgPositioner::new(xdg_shell)?;
+        positioner.set_size(width as i32, height as i32);
+        positioner.set_anchor_rect(start, 0, (end - start).max(1), bar_height as i32);
+        positioner.set_anchor(Anchor::Bottom);
+        positioner.set_gravity(Gravity::Bottom);
+        // Bars at the bottom of the output get their popups above them
+        positioner
+            .set_constraint_adjustment(ConstraintAdjustment::SlideX | ConstraintAdjustment::FlipY);
+        let popup = Popup::from_surface(
+            None,
+            &positioner,
+            qh,
+            compositor.create_surface(qh),
+            xdg_shell,
+        )?;
+        layer.get_popup(popup.xdg_popup());
+        if let Some((seat, serial)) = grab {
+            popup.xdg_popup().grab(seat, *serial);
+        }
+        popup.wl_surface().commit();
+        let viewport = viewporter.map(|v| v.get_viewport(popup.wl_surface(), qh, ()));
+        Ok(Self {
+            popup,
+            viewport,
+            rows,
+            selected: None,
+            scale,
+            configured: false,
+            buffer: None,
+        })
+    }
+
+    pub fn popup(&self) -> &Popup {
+        &self.popup
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Select a row, or none. Returns whether the selection changed.
+    pub fn select(&mut self, row: Option<usize>) -> bool {
+        let row = row.filter(|v| self.rows.get(*v).is_some_and(|v| v.selected.is_some()));
+        std::mem::replace(&mut self.selected, row) != row
+    }
+
+    /// Replace the rows with the same ones laid out at a new `scale`.
+    pub fn rescale(&mut self, rows: Vec<Row>, scale: u32) {
+        self.rows = rows;
+        self.scale = scale;
+    }
+
+    /// Get the row at logical `y`.
+    pub fn row_at(&self, y: f64) -> Option<usize> {
+        let mut y = (y * self.scale as f64 / 120.).max(0.) as usize;
+        for (idx, row) in self.rows.iter().enumerate() {
+            let height = row.layout.size().1;
+            if y < height {
+                return Some(idx);
+            }
+            y -= height;
+        }
+        None
+    }
+
+    /// Mark the popup as configured, and paint it.
+    pub fn configure(
+        &mut self,
+        pool: &mut SlotPool,
+        background_color: cli::Color,
+        highlight_color: cli::Color,
+    ) -> Result<(), Error> {
+        self.configured = true;
+        self.paint(pool, background_color, highlight_color)
+    }
+
+    /// Paint the rows on `background_color`, and the selected one on `highlight_color`.
+    ///
+    /// Does nothing until the popup is configured.
+    pub fn paint(
+        &mut self,
+        pool: &mut SlotPool,
+        background_color: cli::Color,
+        highlight_color: cli::Color,
+    ) -> Result<(), Error> {
+        if !self.configured {
+            return Ok(());
+        }
+        let (width, height) = device_size(&self.rows);
+        let surface = self.popup.wl_surface();
+        let (logical_width, logical_height) = logical_size((width, height), self.scale);
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(logical_width as i32, logical_height as i32),
+            None => surface.set_buffer_scale((self.scale / 120) as i32),
+        }
+        let stride = width * 4;
+        let buffer = match self.buffer.take() {
+            Some(buffer)
+                if buffer.height() == height as i32
+                    && buffer.stride() == stride as i32
+                    && pool.canvas(&buffer).is_some() =>
+            {
+                buffer
+            }
+            _ => {
+                pool.create_buffer(
+                    width as i32,
+                    height as i32,
+                    stride as i32,
+                    wl_shm::Format::Argb8888,
+                )
+                .map_err(|e| Error::Unknown(Box::new(e)))?
+                .0
+            }
+        };
+        let mut canvas = crate::paint::Canvas::new(height, width, pool.canvas(&buffer).unwrap());
+        let mut top = 0;
+        for (idx, row) in self.rows.iter().enumerate() {
+            let (row_width, row_height) = row.layout.size();
+            let mut canvas = canvas.slice(0, top, row_width.min(width), row_height)?;
+            match row.selected.as_ref().filter(|_| self.selected == Some(idx)) {
+                Some(layout) => {
+                    canvas.fill_rect(0, 0, canvas.width(), row_height, highlight_color)?;
+                    layout.paint(&mut canvas, Duration::ZERO, &[])?;
+                }
+                None => {
+                    canvas.fill_rect(0, 0, canvas.width(), row_height, background_color)?;
+                    row.layout.paint(&mut canvas, Duration::ZERO, &[])?;
+                }
+            }
+            top += row_height;
+        }
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+        buffer
+            .attach_to(surface)
+            .map_err(|e| Error::Unknown(Box::new(e)))?;
+        surface.commit();
+        self.buffer = Some(buffer);
+        Ok(())
+    }
+}
+
+/// Get the size of the buffer fitting `rows`, in device pixels.
+fn device_size(rows: &[Row]) -> (usize, usize) {
+    rows.iter()
+        .map(|v| v.layout.size())
+        .fold((0, 0), |(width, height), (w, h)| (width.max(w), height + h))
+}
+
+/// Get the logical size of a surface with a buffer of device size `(width, height)`.
+fn logical_size((width, height): (usize, usize), scale: u32) -> (u32, u32) {
+    let logical = |v: usize| (v as u32 * 120).div_ceil(scale).max(1);
+    (logical(width), logical(height))
+}
+
+impl Drop for MarkupPopup {
+    fn drop(&mut self) {
+        if let Some(viewport) = &self.viewport {
+            viewport.destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logical_size_covers_the_buffer() {
+        assert_eq!(logical_size((100, 20), 120), (100, 20));
+        assert_eq!(logical_size((100, 20), 240), (50, 10));
+        // Fractional scales round up, so that nothing is cut
+        assert_eq!(logical_size((101, 3), 180), (68, 2));
+        assert_eq!(logical_size((0, 0), 120), (1, 1));
+    }
+}
@@ -1,57 +1,278 @@
-use crate::paint::Paint;
-use crate::{
-    cli::{self, Color},
-    paint::Paintable,
-    parse::StyledStringPart,
-};
-use ab_glyph::Font;
-use ab_glyph::ScaleFont;
-use ab_glyph::{FontArc, PxScaleFont};
+use crate::error::Error;
+use crate::font::{Face, Fallback};
+use crate::icon::{load_image, IconCache};
+use crate::layout::{Action, Context, Layout};
+use crate::paint::{Paint, Pixmap};
+use crate::parse::Trigger;
+use crate::popup::{MarkupPopup, Row};
+use crate::{cli, paint::Paintable, parse::StyledStringPart};
 use sctk::compositor::{self, CompositorHandler};
 use sctk::output::{self, OutputHandler};
 use sctk::registry::ProvidesRegistryState;
+use sctk::shell::wlr_layer::KeyboardInteractivity;
+use sctk::shell::xdg::popup::{Popup, PopupConfigure, PopupHandler};
 use sctk::shell::xdg::window::{self, Window, WindowConfigure};
+use sctk::shell::xdg::XdgShell;
 use sctk::shell::WaylandSurface;
 use sctk::shm::slot;
 use smithay_client_toolkit as sctk;
-use smithay_client_toolkit::seat::pointer::PointerEventKind;
+use smithay_client_toolkit::seat::keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers};
+use smithay_client_toolkit::seat::pointer::{
+    CursorIcon, PointerEventKind, ThemeSpec, ThemedPointer,
+};
+use smithay_client_toolkit::seat::touch::TouchHandler;
+use smithay_client_toolkit::seat::Capability;
 use smithay_client_toolkit::shm::slot::Buffer;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
-use std::sync::Condvar;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wayland_client::protocol::wl_callback::{self, WlCallback};
+use wayland_client::protocol::wl_keyboard::WlKeyboard;
 use wayland_client::protocol::wl_output::{Transform, WlOutput};
+use wayland_client::protocol::wl_seat::WlSeat;
 use wayland_client::protocol::wl_surface::WlSurface;
-use wayland_client::{protocol, Connection, QueueHandle};
-
-const TEXT_SIZE: f32 = 20.;
-
-fn x_height<F>(font: &PxScaleFont<F>, scale: f32) -> f32
-where
-    F: Font,
-{
-    font.outline_glyph(font.glyph_id('x').with_scale(scale))
-        .unwrap()
-        .px_bounds()
-        .height()
-}
+use wayland_client::protocol::wl_touch::WlTouch;
+use wayland_client::{protocol, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1;
+use wayland_protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::{
+    self, WpFractionalScaleV1,
+};
+use wayland_protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use wayland_protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
 
 pub struct Bar {
     config: cli::Config,
+    connection: Connection,
     registry: sctk::registry::RegistryState,
+    compositor_state: compositor::CompositorState,
+    /// Needed for tooltips, which are xdg popups.
+    xdg_shell: Option<XdgShell>,
     output_state: output::OutputState,
     shm: sctk::shm::Shm,
     pool: slot::SlotPool,
     buffer: Option<Buffer>,
+    /// In logical pixels.
     width: u32,
     height: u32,
+    /// Device pixels per logical one, in 120ths as `wp_fractional_scale_v1` reports it.
+    scale: u32,
+    /// The transform of the output, which the buffer is rendered in.
+    transform: Transform,
+    /// Whether the bar has to be repainted even without new data.
+    dirty: bool,
+    /// Whether a frame callback was requested and hasn't come yet.
+    frame_pending: bool,
+    /// Only bound when the compositor has both fractional scales and viewports.
+    fractional_scale: Option<WpFractionalScaleV1>,
+    viewport: Option<WpViewport>,
+    viewporter: Option<WpViewporter>,
     layer: sctk::shell::wlr_layer::LayerSurface,
     req_exit: bool,
     queue_handler: QueueHandle<Bar>,
     seat_state: sctk::seat::SeatState,
-    pointer: Option<wayland_client::protocol::wl_pointer::WlPointer>,
+    seats: Vec<SeatInput>,
     data: Arc<Mutex<(String, bool)>>,
-    condvar: Arc<Condvar>,
-    fonts: Vec<PxScaleFont<FontArc>>,
+    fonts: Vec<Face>,
+    /// The fonts scaled to device pixels, and the scale they were scaled for.
+    device_fonts: Vec<Face>,
+    device_fonts_scale: u32,
+    fallback: Option<Fallback>,
+    icons: IconCache,
+    series: HashMap<String, VecDeque<f32>>,
+    /// The last layout, reused until new data comes.
+    layout: Option<Layout>,
+    /// The content of marquees, and when it last changed.
+    marquee_keys: Vec<String>,
+    marquee_since: Instant,
+    /// The rendered background, if it's more than a plain color.
+    background: Option<Pixmap>,
+    /// The ids of the action areas under any pointer.
+    hovered: Vec<usize>,
+    tooltip: Option<Tooltip>,
+    /// Wakes the event loop when a tooltip is due.
+    tooltip_timer: Timer,
+    menu: Option<Menu>,
+    /// Whether clicks give the bar the keyboard, taking it from the focused window.
+    keyboard_interactive: bool,
+}
+
+/// The input devices of a seat, and their state.
+struct SeatInput {
+    seat: WlSeat,
+    pointer: Option<ThemedPointer>,
+    keyboard: Option<WlKeyboard>,
+    touch: Option<WlTouch>,
+    /// The pointer position, in logical pixels, if it's on the bar.
+    pointer_x: Option<f64>,
+    /// The cursor last set, which is unset when the pointer enters a surface.
+    cursor: Option<CursorIcon>,
+    /// The serial of the last button press, which menus need to grab the seat.
+    press_serial: u32,
+    modifiers: HeldModifiers,
+    /// The fingers down, by touch id.
+    touches: HashMap<i32, TouchPoint>,
+}
+
+impl SeatInput {
+    fn new(seat: WlSeat) -> Self {
+        Self {
+            seat,
+            pointer: None,
+            keyboard: None,
+            touch: None,
+            pointer_x: None,
+            cursor: None,
+            press_serial: 0,
+            modifiers: Default::default(),
+            touches: HashMap::new(),
+        }
+    }
+
+    /// Release the device of `capability`, if there's one.
+    fn release(&mut self, capability: Capability) {
+        match capability {
+            Capability::Pointer => {
+                // Released when dropped
+                self.pointer = None;
+                self.pointer_x = None;
+                self.cursor = None;
+            }
+            Capability::Keyboard => {
+                if let Some(keyboard) = self.keyboard.take() {
+                    if keyboard.version() >= 3 {
+                        keyboard.release();
+                    }
+                }
+                self.modifiers.leave();
+            }
+            Capability::Touch => {
+                if let Some(touch) = self.touch.take() {
+                    if touch.version() >= 3 {
+                        touch.release();
+                    }
+                }
+                self.touches.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The modifier keys held on a keyboard.
+///
+/// They are only sent while the keyboard is on the bar, so they're forgotten when it leaves.
+#[derive(Default)]
+struct HeldModifiers(crate::parse::Modifiers);
+
+impl HeldModifiers {
+    fn update(&mut self, modifiers: Modifiers) {
+        self.0 = crate::parse::Modifiers {
+            shift: modifiers.shift,
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            logo: modifiers.logo,
+        };
+    }
+
+    fn leave(&mut self) {
+        self.0 = Default::default();
+    }
+
+    fn held(&self) -> crate::parse::Modifiers {
+        self.0
+    }
+}
+
+impl Drop for SeatInput {
+    fn drop(&mut self) {
+        for capability in [Capability::Pointer, Capability::Keyboard, Capability::Touch] {
+            self.release(capability);
+        }
+    }
+}
+
+/// A finger on the bar or on a menu.
+struct TouchPoint {
+    surface: WlSurface,
+    /// Where it went down and where it is now, in logical pixels.
+    start: (f64, f64),
+    position: (f64, f64),
+    since: Instant,
+    serial: u32,
+}
+
+/// An open menu, and the labels and commands of its items.
+struct Menu {
+    popup: MarkupPopup,
+    labels: Vec<String>,
+    cmds: Vec<String>,
+}
+
+/// The tooltip of the area under the pointer.
+struct Tooltip {
+    /// The id of the action area.
+    id: usize,
+    text: String,
+    /// When the pointer came onto the area.
+    since: Instant,
+    popup: Option<MarkupPopup>,
+    /// Whether the popup was opened, which happens once per time the area is entered.
+    opened: bool,
+}
+
+/// Wakes the event loop from other threads, so that new data gets drawn.
+#[derive(Clone)]
+pub struct Waker {
+    connection: Connection,
+    queue_handler: QueueHandle<Bar>,
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        self.connection.display().sync(&self.queue_handler, ());
+        if let Err(e) = self.connection.flush() {
+            log::error!("Cannot wake the event loop: {}", e);
+        }
+    }
+}
+
+/// Wakes the event loop at a given time, from a single thread.
+struct Timer {
+    deadlines: mpsc::Sender<Instant>,
+}
+
+impl Timer {
+    fn new(wake: impl Fn() + Send + 'static) -> Self {
+        let (deadlines, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut deadline: Option<Instant> = None;
+            loop {
+                let received = match deadline {
+                    Some(deadline) => {
+                        receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => receiver.recv().map_err(RecvTimeoutError::from),
+                };
+                deadline = match received {
+                    Ok(deadline) => Some(deadline),
+                    Err(RecvTimeoutError::Timeout) => {
+                        wake();
+                        None
+                    }
+                    // The bar is gone
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+            }
+        });
+        Self { deadlines }
+    }
+
+    /// Wake the event loop at `deadline`, instead of the time set before.
+    fn wake_at(&self, deadline: Instant) {
+        let _ = self.deadlines.send(deadline);
+    }
 }
 
 impl ProvidesRegistryState for Bar {
@@ -81,9 +302,12 @@ impl CompositorHandler for Bar {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _surface: &WlSurface,
-        _new_factor: i32,
+        new_factor: i32,
     ) {
-        // Ignored
+        // Fractional scales are more precise when available
+        if self.fractional_scale.is_none() {
+            self.rescale(new_factor.max(1) as u32 * 120);
+        }
     }
 
     fn transform_changed(
@@ -91,9 +315,10 @@ impl CompositorHandler for Bar {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _surface: &WlSurface,
-        _new_transform: Transform,
+        new_transform: Transform,
     ) {
-        // Ignored
+        self.transform = new_transform;
+        self.dirty = true;
     }
 
     fn frame(
@@ -103,6 +328,7 @@ impl CompositorHandler for Bar {
         _surface: &WlSurface,
         _time: u32,
     ) {
+        self.frame_pending = false;
         self.draw();
     }
 
@@ -127,210 +353,460 @@ impl CompositorHandler for Bar {
     }
 }
 
-#[derive(Debug)]
-struct Action {
-    button: u8,
-    cmd: String,
-    start: usize,
-    end: usize,
-}
+impl Bar {
+    /// Get device pixels per logical one.
+    fn scale_factor(&self) -> f32 {
+        self.scale as f32 / 120.
+    }
 
-enum Command {
-    Text(TextCommand),
-    Underline(LineCommand),
-    Overline(LineCommand),
-}
+    /// Get the size of the bar in device pixels.
+    fn device_size(&self) -> (u32, u32) {
+        let scaled = |v: u32| (v * self.scale + 60) / 120;
+        (scaled(self.width), scaled(self.height))
+    }
 
-impl Command {
-    fn into_offset(self, offset: usize) -> Self {
-        match self {
-            Command::Text(text_command) => Command::Text(text_command.into_offset(offset)),
-            Command::Underline(line_command) => {
-                Command::Underline(line_command.into_offset(offset))
-            }
-            Command::Overline(line_command) => Command::Overline(line_command.into_offset(offset)),
+    /// Convert a pointer position to the device pixels actions are laid out in.
+    fn device_x(&self, x: f64) -> usize {
+        (x * self.scale_factor() as f64) as usize
+    }
+
+    /// Scale the fonts added since the last call, or all of them if the scale changed.
+    fn update_device_fonts(&mut self) {
+        if self.device_fonts_scale != self.scale {
+            self.device_fonts.clear();
+            self.device_fonts_scale = self.scale;
         }
+        let factor = self.scale_factor();
+        let scaled = self.device_fonts.len();
+        self.device_fonts
+            .extend(self.fonts[scaled..].iter().map(|v| v.scaled(factor)));
     }
-}
 
-struct LineCommand {
-    color: Color,
-    start: usize,
-    end: usize,
-}
+    /// Get the action areas under logical `x`.
+    fn actions_at(&self, x: f64) -> impl Iterator<Item = &Action> {
+        let x = self.device_x(x);
+        self.layout
+            .iter()
+            .flat_map(Layout::actions)
+            .filter(move |v| (v.start..v.end).contains(&x))
+    }
 
-impl LineCommand {
-    pub fn offset(&mut self, offset: usize) {
-        self.start += offset;
-        self.end += offset;
+    /// Get the ids of the action areas under any pointer.
+    fn hovered_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<_> = self
+            .seats
+            .iter()
+            .filter_map(|v| v.pointer_x)
+            .flat_map(|x| self.actions_at(x))
+            .map(|v| v.id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
     }
 
-    pub fn into_offset(mut self, offset: usize) -> Self {
-        self.offset(offset);
-        self
+    /// Get the index of the seat `seat`, which is added if it's new.
+    fn seat_index(&mut self, seat: &WlSeat) -> usize {
+        match self.seats.iter().position(|v| v.seat == *seat) {
+            Some(idx) => idx,
+            None => {
+                self.seats.push(SeatInput::new(seat.clone()));
+                self.seats.len() - 1
+            }
+        }
     }
-}
 
-struct TextCommand {
-    string: String,
-    fg: Color,
-    bg: Color,
-    start: usize,
-    end: usize,
-}
+    /// Move the pointer of the seat at `idx` to logical `x`, or off the bar.
+    fn hover(&mut self, idx: usize, x: Option<f64>) {
+        self.seats[idx].pointer_x = x;
+        self.update_cursor();
+        self.update_hover();
+    }
 
-impl Action {
-    pub fn offset(&mut self, offset: usize) {
-        self.start += offset;
-        self.end += offset;
+    /// Follow the areas under the pointers.
+    ///
+    /// Runs the enter and leave actions of the areas crossed, and repaints hover colors.
+    fn update_hover(&mut self) {
+        let hovered = self.hovered_ids();
+        if hovered == self.hovered {
+            return;
+        }
+        for action in self.layout.iter().flat_map(Layout::actions) {
+            let was_hovered = self.hovered.contains(&action.id);
+            let is_hovered = hovered.contains(&action.id);
+            match action.trigger {
+                Trigger::Enter if is_hovered && !was_hovered => println!("{}", action.cmd),
+                Trigger::Leave if was_hovered && !is_hovered => println!("{}", action.cmd),
+                _ => {}
+            }
+        }
+        self.hovered = hovered;
+        self.update_tooltip();
+        self.dirty = true;
+        self.draw();
     }
 
-    pub fn into_offset(mut self, offset: usize) -> Self {
-        self.offset(offset);
-        self
+    /// Show a hand over clickable areas, and the default arrow elsewhere.
+    fn update_cursor(&mut self) {
+        for idx in 0..self.seats.len() {
+            let clickable = self.seats[idx].pointer_x.is_some_and(|x| {
+                self.actions_at(x)
+                    .any(|v| matches!(v.trigger, Trigger::Button(..) | Trigger::Menu(..)))
+            });
+            let icon = if clickable {
+                CursorIcon::Pointer
+            } else {
+                CursorIcon::Default
+            };
+            let input = &mut self.seats[idx];
+            let Some(pointer) = input
+                .pointer
+                .as_ref()
+                .filter(|_| input.cursor != Some(icon))
+            else {
+                continue;
+            };
+            match pointer.set_cursor(&self.connection, icon) {
+                Ok(()) => input.cursor = Some(icon),
+                Err(e) => log::warn!("Cannot set the cursor: {}", e),
+            }
+        }
     }
-}
 
-impl TextCommand {
-    pub fn offset(&mut self, offset: usize) {
-        self.start += offset;
-        self.end += offset;
+    /// Follow the tooltip area under the pointer, whose tooltip opens after a delay.
+    fn update_tooltip(&mut self) {
+        let target = self
+            .layout
+            .iter()
+            .flat_map(Layout::actions)
+            .find(|v| v.trigger == Trigger::Tooltip && self.hovered.contains(&v.id))
+            .map(|v| (v.id, v.cmd.clone()));
+        match (&mut self.tooltip, target) {
+            (Some(tooltip), Some((id, text))) if tooltip.id == id => {
+                if tooltip.text != text {
+                    tooltip.text = text;
+                    // Open tooltips follow their content at once
+                    if tooltip.popup.is_some() {
+                        self.open_tooltip();
+                    }
+                }
+            }
+            (_, Some((id, text))) => {
+                let since = Instant::now();
+                self.tooltip = Some(Tooltip {
+                    id,
+                    text,
+                    since,
+                    popup: None,
+                    opened: false,
+                });
+                self.tooltip_timer
+                    .wake_at(since + self.config.tooltip_delay());
+            }
+            (_, None) => self.tooltip = None,
+        }
     }
 
-    pub fn into_offset(mut self, offset: usize) -> Self {
-        self.offset(offset);
-        self
+    /// Open the tooltip if the pointer rested on its area long enough.
+    fn show_tooltip(&mut self) {
+        if self
+            .tooltip
+            .as_ref()
+            .is_some_and(|v| !v.opened && v.since.elapsed() >= self.config.tooltip_delay())
+        {
+            self.open_tooltip();
+        }
     }
-}
 
-impl Bar {
-    /// Get right font for a character, seeking in all fonts registred in the `fonts` vec.
+    /// Open a popup for the tooltip, replacing the open one.
+    fn open_tooltip(&mut self) {
+        let Some(tooltip) = &self.tooltip else {
+            return;
+        };
+        let popup = self
+            .popup_rows(&[&tooltip.text], false)
+            .and_then(|rows| self.open_popup(tooltip.id, rows, None));
+        let popup = match popup {
+            Ok(popup) => popup,
+            Err(e) => {
+                log::error!("Cannot open tooltip {}: {}", tooltip.text, e);
+                return;
+            }
+        };
+        #[cfg(feature = "logs")]
+        log::info!("Opening tooltip of action #{}", tooltip.id);
+        if let Some(tooltip) = &mut self.tooltip {
+            tooltip.popup = Some(popup);
+            tooltip.opened = true;
+        }
+    }
+
+    /// Get the seat of the touch device `touch`.
+    fn touch_seat(&mut self, touch: &WlTouch) -> Option<&mut SeatInput> {
+        self.seats
+            .iter_mut()
+            .find(|v| v.touch.as_ref() == Some(touch))
+    }
+
+    /// Run the action of `button` at logical `x`, with the modifiers held on `seat`, or open its
+    /// menu grabbing `seat` with the input `serial`.
     ///
-    /// The last font was returned if there're no suitable font.
-    fn get_width(&self, string: &str) -> f32 {
-        let text_obj = crate::paint::Text::new(
-            string.to_owned(),
-            self.fonts.clone(),
-            self.config.foreground_color(),
-            self.config.background_color(),
-        );
-        let (width, _) = text_obj.get_region();
-        width
-    }
-
-    fn parse_to_actions(&self) -> Result<Vec<Action>, ()> {
-        let mut lcursor = 0;
-        let mut rcursor = 0;
-        let mut ccursor = 0;
-        let mut align = crate::parse::Align::Left;
-        let mut pending = None;
-        let data = self.data.lock().unwrap();
-        let mut left = vec![];
-        let mut right = vec![];
-        let mut center = vec![];
-        for i in data
-            .0
-            .parse::<crate::parse::StyledString>()
-            .map_err(|_| ())?
-            .into_content()
-        {
-            match i {
-                StyledStringPart::Style(_) => {} // Styles are irrelevant to action
-                // handling
-                StyledStringPart::String(string) => match align {
-                    crate::parse::Align::Left => {
-                        lcursor += self.get_width(&string) as usize;
-                    }
-                    crate::parse::Align::Right => {
-                        rcursor += self.get_width(&string) as usize;
-                    }
-                    crate::parse::Align::Center => {
-                        ccursor += self.get_width(&string) as usize;
-                    }
-                },
-                StyledStringPart::Action(action) => {
-                    let (button, cmd) = action.into_tuple();
-                    pending = Some(Action {
-                        button,
-                        cmd,
-                        start: match align {
-                            crate::parse::Align::Left => lcursor,
-                            crate::parse::Align::Center => ccursor,
-                            crate::parse::Align::Right => rcursor,
-                        },
-                        end: 0, // Temp
-                    });
-                }
-                StyledStringPart::ActionEnd => {
-                    if let Some(pending) = std::mem::take(&mut pending) {
-                        match align {
-                            crate::parse::Align::Left => left.push(Action {
-                                end: lcursor,
-                                ..pending
-                            }),
-                            crate::parse::Align::Center => center.push(Action {
-                                end: ccursor,
-                                ..pending
-                            }),
-                            crate::parse::Align::Right => right.push(Action {
-                                end: rcursor,
-                                ..pending
-                            }),
-                        }
-                    }
+    /// Returns the number of the action, if any.
+    fn click(&mut self, seat: &WlSeat, button: u8, x: f64, serial: u32) -> Option<usize> {
+        let modifiers = self
+            .seats
+            .iter()
+            .find(|v| v.seat == *seat)
+            .map(|v| v.modifiers.held())
+            .unwrap_or_default();
+        let x = self.device_x(x);
+        let (number, action) = self
+            .layout
+            .as_ref()?
+            .click(x, button, modifiers)
+            .map(|(number, action)| (number, action.clone()))?;
+        match action.trigger {
+            Trigger::Menu(..) => self.open_menu(action.id, &action.cmd, (seat.clone(), serial)),
+            _ => println!("{}", action.cmd),
+        }
+        Some(number)
+    }
+
+    /// Open the menu of the action area `id`, grabbing the seat of `grab` after its input serial.
+    fn open_menu(&mut self, id: usize, spec: &str, grab: (WlSeat, u32)) {
+        let items = crate::parse::menu_items(spec);
+        let labels: Vec<_> = items.iter().map(|v| v.label.as_str()).collect();
+        // Layer surfaces only get the keyboard of their popups when they may have it
+        self.set_keyboard_interactive(true);
+        let popup = self
+            .popup_rows(&labels, true)
+            .and_then(|rows| self.open_popup(id, rows, Some(&grab)));
+        match popup {
+            Ok(popup) => {
+                #[cfg(feature = "logs")]
+                log::info!("Opening menu of action #{}", id);
+                // Tooltips would cover the menu
+                if let Some(tooltip) = &mut self.tooltip {
+                    tooltip.popup = None;
                 }
-                StyledStringPart::Swap => {} // Styles are irrelevant to action
-                StyledStringPart::Align(align_) => {
-                    if pending.is_some() {
-                        log::error!("Cannot change align in actions!");
-                        continue;
+                let (labels, cmds) = items.into_iter().map(|v| (v.label, v.cmd)).unzip();
+                self.menu = Some(Menu {
+                    popup,
+                    labels,
+                    cmds,
+                });
+            }
+            Err(e) => {
+                log::error!("Cannot open menu {}: {}", spec, e);
+                self.close_menu();
+            }
+        }
+    }
+
+    fn close_menu(&mut self) {
+        self.menu = None;
+        self.update_keyboard_interactivity();
+    }
+
+    /// Let clicks give the bar the keyboard only while a menu is open or a binding needs
+    /// modifiers, since it's taken from the focused window.
+    fn update_keyboard_interactivity(&mut self) {
+        let interactive =
+            self.menu.is_some() || self.layout.as_ref().is_some_and(Layout::needs_modifiers);
+        self.set_keyboard_interactive(interactive);
+    }
+
+    fn set_keyboard_interactive(&mut self, interactive: bool) {
+        if interactive == self.keyboard_interactive {
+            return;
+        }
+        self.keyboard_interactive = interactive;
+        self.layer.set_keyboard_interactivity(if interactive {
+            KeyboardInteractivity::OnDemand
+        } else {
+            KeyboardInteractivity::None
+        });
+        self.layer.commit();
+    }
+
+    /// Print the command of the menu item `row`, and close the menu.
+    fn choose(&mut self, row: usize) {
+        if let Some(cmd) = self.menu.as_ref().and_then(|v| v.cmds.get(row)) {
+            #[cfg(feature = "logs")]
+            log::info!("Menu item #{} chosen", row);
+            println!("{}", cmd);
+        }
+        self.close_menu();
+    }
+
+    /// Select the menu item `row`, or none, and repaint the menu if that changed.
+    fn select(&mut self, row: Option<usize>) {
+        let Some(menu) = &mut self.menu else {
+            return;
+        };
+        if menu.popup.select(row) {
+            let result = menu.popup.paint(
+                &mut self.pool,
+                self.config.background_color(),
+                self.config.foreground_color(),
+            );
+            if let Err(e) = result {
+                log::error!("Cannot paint menu: {}", e);
+            }
+        }
+    }
+
+    /// Lay out a popup row for each of `labels`, which may be selected if `selectable`.
+    fn popup_rows(&self, labels: &[&str], selectable: bool) -> Result<Vec<Row>, String> {
+        if labels.is_empty() {
+            return Err("Nothing to show".into());
+        }
+        let ctx = self.context();
+        // Whole scales need buffers of a whole number of logical pixels
+        let step = match self.viewport {
+            Some(_) => 1,
+            None => self.scale as usize / 120,
+        };
+        let mut width = 0;
+        for label in labels {
+            width = width.max(Layout::measure(&ctx, label)?);
+        }
+        let width = width.next_multiple_of(step);
+        // Selected rows have their colors swapped
+        let selected_ctx = Context {
+            width,
+            foreground_color: ctx.background_color,
+            background_color: ctx.foreground_color,
+            ..self.context()
+        };
+        let ctx = Context { width, ..ctx };
+        labels
+            .iter()
+            .map(|label| {
+                Ok(Row {
+                    layout: Layout::new(&ctx, label)?,
+                    selected: match selectable {
+                        true => Some(Layout::new(&selected_ctx, label)?),
+                        false => None,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Open a popup of `rows` under the action area `id`.
+    fn open_popup(
+        &self,
+        id: usize,
+        rows: Vec<Row>,
+        grab: Option<&(WlSeat, u32)>,
+    ) -> Result<MarkupPopup, String> {
+        let xdg_shell = self.xdg_shell.as_ref().ok_or("No xdg shell")?;
+        let area = self
+            .layout
+            .iter()
+            .flat_map(Layout::actions)
+            .find(|v| v.id == id)
+            .ok_or("No such action area")?;
+        let logical = |v: usize| (v as f32 / self.scale_factor()).round() as i32;
+        MarkupPopup::new(
+            &self.queue_handler,
+            &self.compositor_state,
+            xdg_shell,
+            self.viewporter.as_ref(),
+            &self.layer,
+            (logical(area.start), logical(area.end)),
+            self.height,
+            rows,
+            self.scale,
+            grab,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Render the bar and its open popups at a new scale, in 120ths.
+    fn rescale(&mut self, scale: u32) {
+        if scale == self.scale {
+            return;
+        }
+        self.scale = scale;
+        self.dirty = true;
+        self.update_device_fonts();
+        // Popups are placed under the areas of the new layout
+        self.draw();
+        if self.tooltip.as_ref().is_some_and(|v| v.popup.is_some()) {
+            self.open_tooltip();
+        }
+        if let Some(menu) = &self.menu {
+            let labels: Vec<_> = menu.labels.iter().map(String::as_str).collect();
+            match self.popup_rows(&labels, true) {
+                Ok(rows) => {
+                    let menu = self.menu.as_mut().unwrap();
+                    menu.popup.rescale(rows, scale);
+                    let result = menu.popup.paint(
+                        &mut self.pool,
+                        self.config.background_color(),
+                        self.config.foreground_color(),
+                    );
+                    if let Err(e) = result {
+                        log::error!("Cannot paint menu: {}", e);
                     }
-                    align = align_;
                 }
-                StyledStringPart::Offset(offset) => match align {
-                    crate::parse::Align::Left => lcursor += offset,
-                    crate::parse::Align::Center => ccursor += offset,
-                    crate::parse::Align::Right => rcursor += offset,
-                },
-                StyledStringPart::Attribute {
-                    attribute: _,
-                    action: _,
-                } => {} // Attributes are
-                        // irrelevant to action
-            }
-        }
-        if let Some(pending) = pending {
-            log::warn!("Unclosed action block; check your feeding script");
-            match align {
-                crate::parse::Align::Left => left.push(Action {
-                    end: lcursor,
-                    ..pending
-                }),
-                crate::parse::Align::Center => center.push(Action {
-                    end: ccursor,
-                    ..pending
-                }),
-                crate::parse::Align::Right => right.push(Action {
-                    end: rcursor,
-                    ..pending
-                }),
-            }
-        }
-        let retval = left
-            .into_iter()
-            .map(|v| v.into_offset(5))
-            .chain(
-                center
-                    .into_iter()
-                    .map(|v| v.into_offset((self.width as usize - ccursor) / 2)),
-            )
-            .chain(
-                right
-                    .into_iter()
-                    .map(|v| v.into_offset(self.width as usize - 5 - rcursor)),
-            )
-            .collect();
-        Ok(retval)
+                Err(e) => log::error!("Cannot lay out menu: {}", e),
+            }
+        }
+    }
+
+    /// Get the top and the height of the text line, centered in the bar.
+    fn line_box(&self) -> (usize, usize) {
+        let (ascent, descent) = crate::paint::line_metrics(&self.device_fonts);
+        let line_height = (ascent - descent).ceil() as usize;
+        (
+            (self.device_size().1 as usize).saturating_sub(line_height) / 2,
+            line_height,
+        )
+    }
+
+    /// Get what the layout needs to know about the bar.
+    fn context(&self) -> Context<'_> {
+        let (line_top, line_height) = self.line_box();
+        let (width, height) = self.device_size();
+        Context {
+            fonts: &self.device_fonts,
+            icons: &self.icons,
+            series: &self.series,
+            foreground_color: self.config.foreground_color(),
+            background_color: self.config.background_color(),
+            width: width as usize,
+            height: height as usize,
+            line_top,
+            line_height,
+            marquee_speed: self.config.marquee_speed(),
+            marquee_pause: self.config.marquee_pause(),
+            scale: self.scale_factor(),
+        }
+    }
+
+    /// Append the values of named graphs to their series.
+    fn update_series(&mut self, data: &str) {
+        let Ok(string) = data.parse::<crate::parse::StyledString>() else {
+            return;
+        };
+        for part in string.into_content() {
+            let StyledStringPart::Graph(graph) = part else {
+                continue;
+            };
+            let graph_width = graph.width();
+            let Some(name) = graph.name().map(str::to_owned) else {
+                continue;
+            };
+            let series = self.series.entry(name).or_default();
+            series.extend(graph.into_values());
+            // Keep at most one value per pixel
+            while series.len() > graph_width {
+                series.pop_front();
+            }
+        }
     }
 
     pub fn new(config: cli::Config) -> (Self, wayland_client::EventQueue<Self>) {
@@ -352,287 +828,264 @@ impl Bar {
             config.name(),
             None,
         );
-        layer.set_anchor(
-            sctk::shell::wlr_layer::Anchor::TOP
-                | sctk::shell::wlr_layer::Anchor::LEFT
-                | sctk::shell::wlr_layer::Anchor::RIGHT,
-        );
-        layer.set_size(0, 30);
-        // Default to no keyboard interactive
-        layer.commit();
-
         let pool = sctk::shm::slot::SlotPool::new(122880, &shm).unwrap();
 
         let seat_state = smithay_client_toolkit::seat::SeatState::new(&globals, &qh);
 
-        let fontconfig = font_kit::source::SystemSource::new();
-        let fonts: Vec<FontArc> = config
-            .fonts()
-            .iter()
-            .map(|v| {
-                if let font_kit::handle::Handle::Path { path, font_index } = fontconfig
-                    .select_best_match(
-                        &[font_kit::family_name::FamilyName::Title(v.to_string())],
-                        &Default::default(),
-                    )
-                    .unwrap_or_else(|_| {
-                        fontconfig
-                            .select_best_match(
-                                &[font_kit::family_name::FamilyName::Title(v.to_string())],
-                                &Default::default(),
-                            )
-                            .map_err(|_| crate::error::Error::FontNotFound)
-                            .unwrap()
-                    })
-                {
-                    ab_glyph::FontVec::try_from_vec_and_index(
-                        std::fs::read(path).unwrap(),
-                        font_index,
-                    )
-                    .map_err(|_| crate::error::Error::FontNotFound)
-                    .unwrap()
-                    .into()
-                } else {
-                    panic!("Invalid font")
-                }
-            })
-            .collect();
-        let primary_font = fonts.first().unwrap();
-        let base_x_height = x_height(&primary_font.as_scaled(TEXT_SIZE), TEXT_SIZE);
-        let fonts = fonts
-            .into_iter()
-            .map(|v| {
-                let v_x_height = x_height(&v.as_scaled(TEXT_SIZE), TEXT_SIZE);
-                let x_height_ratio = base_x_height / v_x_height;
-                v.into_scaled(TEXT_SIZE * x_height_ratio)
-            })
-            .collect();
+        // Fractional scales need a viewport to map the buffer back to the surface size
+        let viewporter = globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()).ok();
+        let fractional_scale_manager = globals
+            .bind::<WpFractionalScaleManagerV1, _, _>(&qh, 1..=1, ())
+            .ok();
+        let (viewporter, viewport, fractional_scale) = match (viewporter, fractional_scale_manager)
+        {
+            (Some(viewporter), Some(manager)) => (
+                Some(viewporter.clone()),
+                Some(viewporter.get_viewport(layer.wl_surface(), &qh, ())),
+                Some(manager.get_fractional_scale(layer.wl_surface(), &qh, ())),
+            ),
+            _ => (None, None, None),
+        };
+        let xdg_shell = XdgShell::bind(&globals, &qh).ok();
+
+        let fonts = crate::font::load_fonts(config.fonts()).unwrap();
+
+        let waker = Waker {
+            connection: conn.clone(),
+            queue_handler: qh.clone(),
+        };
+        let tooltip_waker = waker.clone();
+        let tooltip_timer = Timer::new(move || tooltip_waker.wake());
+        // Redraws once the system fonts are indexed, to cover what's shown by then
+        let fallback = config
+            .font_fallback()
+            .then(|| Fallback::new(&fonts, move || waker.wake()));
+
+        let geometry = config.geometry();
+        let (ascent, descent) = crate::paint::line_metrics(&fonts);
+        let height = geometry
+            .height()
+            .unwrap_or((ascent - descent).ceil() as u32 + config.padding() * 2);
+        let mut anchor = sctk::shell::wlr_layer::Anchor::TOP | sctk::shell::wlr_layer::Anchor::LEFT;
+        if geometry.width().is_none() {
+            anchor |= sctk::shell::wlr_layer::Anchor::RIGHT;
+        }
+        layer.set_anchor(anchor);
+        layer.set_margin(geometry.y(), 0, 0, geometry.x());
+        layer.set_size(geometry.width().unwrap_or(0), height);
+        // Default to no keyboard interactive, so that clicks leave the keyboard to the focused
+        // window; menus and bindings with modifiers need it, and turn it on while they're there
+        layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+        layer.commit();
         (
             Bar {
                 config,
+                connection: conn,
                 output_state: output::OutputState::new(&globals, &qh),
                 registry: sctk::registry::RegistryState::new(&globals),
+                compositor_state,
                 req_exit: false,
                 pool,
                 shm,
-                width: 1024,
-                height: 30,
+                width: geometry.width().unwrap_or(1024),
+                height,
+                scale: 120,
+                transform: Transform::Normal,
+                dirty: false,
+                frame_pending: false,
+                fractional_scale,
+                viewport,
+                viewporter,
+                xdg_shell,
                 buffer: None,
                 layer,
                 queue_handler: qh,
                 seat_state,
-                pointer: None,
+                seats: vec![],
                 data: Arc::new(Mutex::new(("".into(), false))),
+                fallback,
+                device_fonts: fonts.clone(),
+                device_fonts_scale: 120,
                 fonts,
-                condvar: Arc::new(Condvar::new()),
+                icons: IconCache::default(),
+                series: HashMap::new(),
+                layout: None,
+                marquee_keys: vec![],
+                marquee_since: Instant::now(),
+                background: None,
+                hovered: vec![],
+                tooltip: None,
+                tooltip_timer,
+                menu: None,
+                keyboard_interactive: false,
             },
             event_queue,
         )
     }
 
+    /// Render the background color, gradient and image of the bar.
+    fn render_background(&self, width: usize, height: usize) -> Pixmap {
+        let mut pixmap = Pixmap::new(width, height);
+        pixmap
+            .fill_rect(0, 0, width, height, self.config.background_color())
+            .unwrap();
+        if let Some(gradient) = self.config.gradient() {
+            let (sin, cos) = gradient.angle().to_radians().sin_cos();
+            // The length of the bar projected on the gradient direction
+            let extent = (width as f32 * cos).abs() + (height as f32 * sin).abs();
+            let mut row = Vec::with_capacity(width);
+            for y in 0..height {
+                let dy = y as f32 + 0.5 - height as f32 / 2.;
+                row.clear();
+                row.extend((0..width).map(|x| {
+                    let dx = x as f32 + 0.5 - width as f32 / 2.;
+                    gradient.color_at((dx * cos + dy * sin) / extent + 0.5)
+                }));
+                pixmap.blend_span(0, y, &row).unwrap();
+            }
+        }
+        if let Some(path) = self.config.background_image() {
+            let image = match self.config.background_image_mode() {
+                cli::ImageMode::Scale => load_image(path, Some((width, height))),
+                cli::ImageMode::Tile => load_image(path, None),
+            };
+            match image {
+                Ok(image) if image.width() != 0 && image.height() != 0 => {
+                    for y in (0..height).step_by(image.height()) {
+                        for x in (0..width).step_by(image.width()) {
+                            image
+                                .paint(
+                                    &mut pixmap
+                                        .slice(
+                                            x,
+                                            y,
+                                            image.width().min(width - x),
+                                            image.height().min(height - y),
+                                        )
+                                        .unwrap(),
+                                )
+                                .unwrap();
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Cannot load background image {}: {}", path.display(), e),
+            }
+        }
+        pixmap
+    }
+
+    /// Paint the bar if anything changed since it was last painted.
     fn draw(&mut self) {
-        let width = self.width;
-        let height = self.height;
-        let stride = self.width * 4;
+        let (width, height) = self.device_size();
 
-        self.layer.set_exclusive_zone(height as i32 + 3);
+        let mut guard = self.data.lock().unwrap();
+        let fresh = std::mem::take(&mut guard.1);
+        let data = guard.0.clone();
+        drop(guard);
+        let dirty = std::mem::take(&mut self.dirty);
+        let indexed = self.fallback.as_mut().is_some_and(Fallback::poll);
+        // Scrolling marquees keep redrawing on each frame, and so do size changes
+        if !fresh
+            && !dirty
+            && !indexed
+            && !self
+                .layout
+                .as_ref()
+                .is_some_and(|v| v.is_animated() || v.size() != (width as usize, height as usize))
+        {
+            return;
+        }
 
-        let mut data = self.data.lock().unwrap();
-        #[cfg(feature = "logs")]
-        log::info!("Pending on condvar...");
-        while !data.1 {
-            data = self.condvar.wait(data).unwrap();
+        // Quarter turns swap the sides of the buffer
+        let (buffer_width, buffer_height) = match self.transform {
+            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+                (height, width)
+            }
+            _ => (width, height),
+        };
+        let stride = buffer_width * 4;
+
+        self.layer.set_exclusive_zone(self.height as i32 + 3);
+        self.layer.wl_surface().set_buffer_transform(self.transform);
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(self.width as i32, self.height as i32),
+            None => self
+                .layer
+                .wl_surface()
+                .set_buffer_scale((self.scale / 120) as i32),
         }
-        data.1 = false;
-        let data = &data.0;
-        #[cfg(feature = "logs")]
-        log::info!("Got new data: {}", data);
-
-        let mut fg = self.config.foreground_color();
-        let mut bg = self.config.background_color();
-        let mut lcursor = 5;
-        let mut rcursor = 5;
-        let mut ccursor = 5;
-        let mut align = crate::parse::Align::Left;
-        let mut left = vec![];
-        let mut right = vec![];
-        let mut center = vec![];
-        let mut pending_overline = None;
-        let mut pending_underline = None;
-        for i in data
-            .parse::<crate::parse::StyledString>()
-            .unwrap()
-            .into_content()
+
+        if (self.config.gradient().is_some() || self.config.background_image().is_some())
+            && self
+                .background
+                .as_ref()
+                .is_none_or(|v| v.width() != width as usize || v.height() != height as usize)
         {
-            match i {
-                StyledStringPart::Style(style) => {
-                    fg = style
-                        .foreground_color()
-                        .into_color(self.config.foreground_color(), fg);
-                    bg = style
-                        .background_color()
-                        .into_color(self.config.background_color(), bg);
-                }
-                StyledStringPart::String(string) => match align {
-                    crate::parse::Align::Left => {
-                        let width = self.get_width(&string) as usize;
-                        left.push(Command::Text(TextCommand {
-                            fg,
-                            bg,
-                            string,
-                            start: lcursor,
-                            end: lcursor + width,
-                        }));
-                        lcursor += width;
-                    }
-                    crate::parse::Align::Right => {
-                        let width = self.get_width(&string) as usize;
-                        right.push(Command::Text(TextCommand {
-                            fg,
-                            bg,
-                            string,
-                            start: rcursor,
-                            end: rcursor + width,
-                        }));
-                        rcursor += width;
-                    }
-                    crate::parse::Align::Center => {
-                        let width = self.get_width(&string) as usize;
-                        center.push(Command::Text(TextCommand {
-                            fg,
-                            bg,
-                            string,
-                            start: ccursor,
-                            end: ccursor + width,
-                        }));
-                        ccursor += width;
-                    }
-                },
-                StyledStringPart::Action(_) => {} // Actions are irrelevant to rendering
-                StyledStringPart::ActionEnd => {} // Actions are irrelevant to rendering
-                StyledStringPart::Swap => {
-                    std::mem::swap(&mut fg, &mut bg);
-                }
-                StyledStringPart::Align(align_) => {
-                    align = align_;
-                }
-                StyledStringPart::Offset(offset) => match align {
-                    crate::parse::Align::Left => lcursor += offset,
-                    crate::parse::Align::Center => ccursor += offset,
-                    crate::parse::Align::Right => rcursor += offset,
-                },
-                StyledStringPart::Attribute { attribute, action } => {
-                    let cursor = match align {
-                        crate::parse::Align::Left => lcursor,
-                        crate::parse::Align::Center => ccursor,
-                        crate::parse::Align::Right => rcursor,
-                    };
-                    match attribute {
-                        crate::parse::Attribute::Underline => {
-                            match action {
-                                crate::parse::AttributeAction::On => {
-                                    pending_underline.get_or_insert(LineCommand {
-                                        color: self.config.foreground_color(),
-                                        start: cursor,
-                                        end: 0, // Temp
-                                    });
-                                }
-                                crate::parse::AttributeAction::Off => {
-                                    if let Some(line) = std::mem::take(&mut pending_underline) {
-                                        match align {
-                                            crate::parse::Align::Left => &mut left,
-                                            crate::parse::Align::Right => &mut right,
-                                            crate::parse::Align::Center => &mut center,
-                                        }.push(Command::Underline(LineCommand {
-                                            end: cursor,
-                                            ..line
-                                        }))
-                                    }
-                                }
-                                crate::parse::AttributeAction::Toggle => {
-                                    if let Some(line) = std::mem::take(&mut pending_underline) {
-                                        match align {
-                                            crate::parse::Align::Left => &mut left,
-                                            crate::parse::Align::Right => &mut right,
-                                            crate::parse::Align::Center => &mut center,
-                                        }.push(Command::Underline(LineCommand {
-                                            end: cursor,
-                                            ..line
-                                        }))
-                                    } else {
-                                        pending_underline.get_or_insert(LineCommand {
-                                            color: self.config.foreground_color(),
-                                            start: cursor,
-                                            end: 0, // Temp
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                        crate::parse::Attribute::Overline => {
-                            match action {
-                                crate::parse::AttributeAction::On => {
-                                    pending_overline.get_or_insert(LineCommand {
-                                        color: self.config.foreground_color(),
-                                        start: cursor,
-                                        end: 0, // Temp
-                                    });
-                                }
-                                crate::parse::AttributeAction::Off => {
-                                    if let Some(line) = std::mem::take(&mut pending_overline) {
-                                        match align {
-                                            crate::parse::Align::Left => &mut left,
-                                            crate::parse::Align::Right => &mut right,
-                                            crate::parse::Align::Center => &mut center,
-                                        }.push(Command::Overline(LineCommand {
-                                            end: cursor,
-                                            ..line
-                                        }))
-                                    }
-                                }
-                                crate::parse::AttributeAction::Toggle => {
-                                    if let Some(line) = std::mem::take(&mut pending_overline) {
-                                        match align {
-                                            crate::parse::Align::Left => &mut left,
-                                            crate::parse::Align::Right => &mut right,
-                                            crate::parse::Align::Center => &mut center,
-                                        }.push(Command::Overline(LineCommand {
-                                            end: cursor,
-                                            ..line
-                                        }))
-                                    } else {
-                                        pending_overline.get_or_insert(LineCommand {
-                                            color: self.config.foreground_color(),
-                                            start: cursor,
-                                            end: 0, // Temp
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+            self.background = Some(self.render_background(width as usize, height as usize));
+        }
+
+        if fresh {
+            #[cfg(feature = "logs")]
+            log::info!("Got new data: {}", data);
+            self.update_series(&data);
+        }
+        if fresh || indexed {
+            if let Some(fallback) = &mut self.fallback {
+                fallback.cover(&mut self.fonts, &data);
             }
         }
-        let cmds = left
-            .into_iter()
-            .map(|v| v.into_offset(5))
-            .chain(
-                center
-                    .into_iter()
-                    .map(|v| v.into_offset((self.width as usize - ccursor) / 2)),
-            )
-            .chain(
-                right
-                    .into_iter()
-                    .map(|v| v.into_offset(self.width as usize - 5 - rcursor)),
-            );
+        self.update_device_fonts();
+        if fresh
+            || indexed
+            || self
+                .layout
+                .as_ref()
+                .is_none_or(|v| v.size() != (width as usize, height as usize))
+        {
+            let layout = Layout::new(&self.context(), &data).unwrap_or_else(|e| {
+                log::error!("Cannot lay out {}: {}", data, e);
+                Layout::default()
+            });
+            let marquee_keys = layout.marquee_keys();
+            if marquee_keys != self.marquee_keys {
+                self.marquee_keys = marquee_keys;
+                self.marquee_since = Instant::now();
+            }
+            self.layout = Some(layout);
+            self.update_keyboard_interactivity();
+            // Areas may have moved under the pointer
+            self.hovered = self.hovered_ids();
+            self.update_tooltip();
+        }
 
+        if self
+            .buffer
+            .as_ref()
+            .is_some_and(|v| v.height() != buffer_height as i32 || v.stride() != stride as i32)
+        {
+            self.buffer = None;
+        }
+        // Transformed buffers get the bar painted upright first
+        let elapsed = self.marquee_since.elapsed();
+        let upright = (self.transform != Transform::Normal).then(|| {
+            let mut pixmap = Pixmap::new(width as usize, height as usize);
+            paint_upright(
+                &mut pixmap,
+                self.background.as_ref(),
+                self.config.background_color(),
+                self.layout.as_ref(),
+                elapsed,
+                &self.hovered,
+            )
+            .unwrap();
+            pixmap
+        });
         let buffer = self.buffer.get_or_insert_with(|| {
             self.pool
                 .create_buffer(
-                    width as i32,
-                    height as i32,
+                    buffer_width as i32,
+                    buffer_height as i32,
                     stride as i32,
                     protocol::wl_shm::Format::Argb8888,
                 )
@@ -645,8 +1098,8 @@ impl Bar {
                 let (second_buffer, canvas) = self
                     .pool
                     .create_buffer(
-                        width as i32,
-                        height as i32,
+                        buffer_width as i32,
+                        buffer_height as i32,
                         stride as i32,
                         protocol::wl_shm::Format::Argb8888,
                     )
@@ -662,84 +1115,33 @@ impl Bar {
         //    stride as usize,
         //    andrew::Endian::Big,
         //);
-        let mut canvas = crate::paint::Canvas::new(height as usize, width as usize, canvas);
-        {
-            //canvas
-            //    .buffer
-            //    .chunks_exact_mut(4)
-            //    .enumerate()
-            //    .for_each(|(_index, chunk)| {
-            //        let array: &mut [u8; 4] = chunk.try_into().unwrap();
-            //        *array = self.config.background_color().into();
-            //    });
-            for y in 0..height {
-                for x in 0..width {
-                    canvas
-                        .draw_pixel(x as usize, y as usize, self.config.background_color())
-                        .unwrap();
-                }
-            }
-
-            for i in cmds {
-                match i {
-                    Command::Text(command) => {
-                        let TextCommand {
-                            string,
-                            fg,
-                            bg,
-                            start,
-                            end: _,
-                        } = command;
-
-                        let text = crate::paint::Text::new(string, self.fonts.clone(), fg, bg);
-
-                        text.paint(
-                            &mut canvas
-                                .slice(
-                                    start,
-                                    5,
-                                    self.width as usize - start,
-                                    self.height as usize - 5,
-                                )
-                                .unwrap(),
-                        )
-                        .unwrap();
-                    }
-                    Command::Underline(command) => {
-                        let LineCommand { color, start, end } = command;
-
-                        for i in start..end {
-                            canvas
-                                .draw_pixel(
-                                    i,
-                                    5 + self.fonts.first().unwrap().height() as usize + 1,
-                                    color,
-                                )
-                                .unwrap();
-                        }
-                    }
-                    Command::Overline(command) => {
-                        let LineCommand { color, start, end } = command;
-
-                        for i in start..end {
-                            canvas
-                                .draw_pixel(i, 4, color)
-                                .unwrap();
-                        }
-                    }
-                }
-            }
-            #[cfg(feature = "logs")]
-            log::info!("Painted");
+        let mut canvas =
+            crate::paint::Canvas::new(buffer_height as usize, buffer_width as usize, canvas);
+        match &upright {
+            Some(pixmap) => pixmap.blit_transformed(&mut canvas, self.transform),
+            None => paint_upright(
+                &mut canvas,
+                self.background.as_ref(),
+                self.config.background_color(),
+                self.layout.as_ref(),
+                elapsed,
+                &self.hovered,
+            ),
         }
+        .unwrap();
+        #[cfg(feature = "logs")]
+        log::info!("Painted");
 
         self.layer
             .wl_surface()
-            .damage_buffer(0, 0, width as i32, height as i32);
+            .damage_buffer(0, 0, buffer_width as i32, buffer_height as i32);
 
-        self.layer
-            .wl_surface()
-            .frame(&self.queue_handler, self.layer.wl_surface().clone());
+        if !self.frame_pending {
+            self.layer
+                .wl_surface()
+                .frame(&self.queue_handler, self.layer.wl_surface().clone());
+            self.frame_pending = true;
+        }
 
         buffer.attach_to(self.layer.wl_surface()).unwrap();
         self.layer.commit();
@@ -753,11 +1155,36 @@ impl Bar {
         self.data.clone()
     }
 
-    pub fn condvar(&self) -> Arc<Condvar> {
-        self.condvar.clone()
+    pub fn waker(&self) -> Waker {
+        Waker {
+            connection: self.connection.clone(),
+            queue_handler: self.queue_handler.clone(),
+        }
     }
 }
 
+/// Paint the background and the content of the bar, as seen on the surface.
+fn paint_upright(
+    canvas: &mut impl Paintable,
+    background: Option<&Pixmap>,
+    background_color: cli::Color,
+    layout: Option<&Layout>,
+    elapsed: Duration,
+    hovered: &[usize],
+) -> Result<(), Error> {
+    match background {
+        Some(background) => background.blit(canvas, 0, 0)?,
+        None => {
+            let (width, height) = (canvas.width(), canvas.height());
+            canvas.fill_rect(0, 0, width, height, background_color)?
+        }
+    }
+    if let Some(layout) = layout {
+        layout.paint(canvas, elapsed, hovered)?;
+    }
+    Ok(())
+}
+
 impl window::WindowHandler for Bar {
     fn request_close(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _window: &Window) {
         self.req_exit = true;
@@ -775,6 +1202,53 @@ impl window::WindowHandler for Bar {
     }
 }
 
+impl PopupHandler for Bar {
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        popup: &Popup,
+        _config: PopupConfigure,
+    ) {
+        if let Some(tooltip) = self
+            .tooltip
+            .as_mut()
+            .and_then(|v| v.popup.as_mut())
+            .filter(|v| v.popup() == popup)
+        {
+            let result = tooltip.configure(
+                &mut self.pool,
+                self.config.background_color(),
+                self.config.foreground_color(),
+            );
+            if let Err(e) = result {
+                log::error!("Cannot paint tooltip: {}", e);
+            }
+        }
+        if let Some(menu) = self.menu.as_mut().filter(|v| v.popup.popup() == popup) {
+            let result = menu.popup.configure(
+                &mut self.pool,
+                self.config.background_color(),
+                self.config.foreground_color(),
+            );
+            if let Err(e) = result {
+                log::error!("Cannot paint menu: {}", e);
+            }
+        }
+    }
+
+    fn done(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, popup: &Popup) {
+        if let Some(tooltip) = &mut self.tooltip {
+            if tooltip.popup.as_ref().is_some_and(|v| v.popup() == popup) {
+                tooltip.popup = None;
+            }
+        }
+        if self.menu.as_ref().is_some_and(|v| v.popup.popup() == popup) {
+            self.close_menu();
+        }
+    }
+}
+
 impl sctk::shm::ShmHandler for Bar {
     fn shm_state(&mut self) -> &mut sctk::shm::Shm {
         &mut self.shm
@@ -799,13 +1273,14 @@ impl sctk::shell::wlr_layer::LayerShellHandler for Bar {
         configure: smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        if configure.new_size == (0, 0) {
-            self.width = 1024;
-            self.height = 30;
-        } else {
+        // Keep the requested size for dimensions left to us
+        if configure.new_size.0 != 0 {
             self.width = configure.new_size.0;
+        }
+        if configure.new_size.1 != 0 {
             self.height = configure.new_size.1;
         }
+        self.dirty = true;
         self.draw();
     }
 }
@@ -815,35 +1290,67 @@ impl sctk::seat::pointer::PointerHandler for Bar {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _pointer: &protocol::wl_pointer::WlPointer,
+        pointer: &protocol::wl_pointer::WlPointer,
         events: &[smithay_client_toolkit::seat::pointer::PointerEvent],
     ) {
+        let Some(idx) = self
+            .seats
+            .iter()
+            .position(|v| v.pointer.as_ref().is_some_and(|v| v.pointer() == pointer))
+        else {
+            return;
+        };
         for event in events {
+            if self
+                .menu
+                .as_ref()
+                .is_some_and(|v| event.surface == *v.popup.popup().wl_surface())
+            {
+                match event.kind {
+                    PointerEventKind::Enter { .. } => {
+                        self.seats[idx].cursor = None;
+                        self.update_cursor();
+                        self.select(self.menu.as_ref().unwrap().popup.row_at(event.position.1));
+                    }
+                    PointerEventKind::Motion { .. } => {
+                        self.select(self.menu.as_ref().unwrap().popup.row_at(event.position.1));
+                    }
+                    PointerEventKind::Leave { .. } => self.select(None),
+                    PointerEventKind::Release { .. } => {
+                        if let Some(row) =
+                            self.menu.as_ref().unwrap().popup.row_at(event.position.1)
+                        {
+                            self.choose(row);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
             if event.surface != *self.layer.wl_surface() {
                 continue;
             }
             match event.kind {
+                PointerEventKind::Enter { .. } => {
+                    // The cursor is unset on every enter
+                    self.seats[idx].cursor = None;
+                    self.hover(idx, Some(event.position.0));
+                }
+                PointerEventKind::Motion { .. } => self.hover(idx, Some(event.position.0)),
+                PointerEventKind::Leave { .. } => self.hover(idx, None),
+                PointerEventKind::Press { serial, .. } => self.seats[idx].press_serial = serial,
                 PointerEventKind::Release { button, .. } => {
-                    let splitted_content = self.parse_to_actions().unwrap();
-                    let mut matched = None;
-                    for (idx, content) in splitted_content.into_iter().enumerate() {
-                        if (content.start..content.end).contains(&(event.position.0 as usize))
-                            && crate::consts::wayland2bar(button)
-                                .is_some_and(|v| v == content.button as u32)
-                        {
-                            let number = idx;
-                            let action = content.cmd;
-                            matched = Some((number, action));
-                        }
-                    }
-                    if let Some((number, action)) = matched {
+                    let seat = self.seats[idx].seat.clone();
+                    let serial = self.seats[idx].press_serial;
+                    let matched = crate::consts::wayland2bar(button)
+                        .and_then(|v| self.click(&seat, v as u8, event.position.0, serial));
+                    if let Some(_number) = matched {
                         #[cfg(feature = "logs")]
                         log::info!(
                             "Pointer release key {} triggering action #{}",
                             button,
-                            number
+                            _number
                         );
-                        println!("{}", action);
                     } else {
                         #[cfg(feature = "logs")]
                         log::info!(
@@ -853,42 +1360,39 @@ impl sctk::seat::pointer::PointerHandler for Bar {
                         );
                     }
                 }
-                PointerEventKind::Axis { vertical, .. } => {
-                    let action = if vertical.discrete > 0 {
-                        5
-                    } else if vertical.discrete < 0 {
-                        4
-                    } else {
-                        0
-                    };
-                    let splitted_content = self.parse_to_actions().unwrap();
-                    let mut matched = None;
-                    for (idx, content) in splitted_content.into_iter().enumerate() {
-                        if (content.start..content.end).contains(&(event.position.0 as usize))
-                            && action == content.button
-                        {
-                            let number = idx;
-                            let action = content.cmd;
-                            matched = Some((number, action));
+                PointerEventKind::Axis {
+                    horizontal,
+                    vertical,
+                    ..
+                } => {
+                    let seat = self.seats[idx].seat.clone();
+                    let serial = self.seats[idx].press_serial;
+                    // Up and down are buttons 4 and 5, left and right are 6 and 7
+                    for (discrete, back, forward) in
+                        [(vertical.discrete, 4, 5), (horizontal.discrete, 6, 7)]
+                    {
+                        let button = match discrete.signum() {
+                            -1 => back,
+                            1 => forward,
+                            _ => continue,
+                        };
+                        let matched = self.click(&seat, button, event.position.0, serial);
+                        if let Some(_number) = matched {
+                            #[cfg(feature = "logs")]
+                            log::info!(
+                                "Mouse wheel rotating as button {} triggering #{}",
+                                button,
+                                _number,
+                            );
+                        } else {
+                            #[cfg(feature = "logs")]
+                            log::info!(
+                                "Mouse wheel rotating as button {} triggering nothing",
+                                button
+                            );
                         }
                     }
-                    if let Some((number, action)) = matched {
-                        #[cfg(feature = "logs")]
-                        log::info!(
-                            "Mouse wheel rotating v {} triggering #{}",
-                            vertical.discrete,
-                            number,
-                        );
-                        println!("{}", action);
-                    } else {
-                        #[cfg(feature = "logs")]
-                        log::info!(
-                            "Mouse wheel rotating v {} triggering nothing",
-                            vertical.discrete,
-                        );
-                    }
                 }
-                _ => {}
             }
         }
     }
@@ -903,9 +1407,9 @@ impl sctk::seat::SeatHandler for Bar {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _seat: protocol::wl_seat::WlSeat,
+        seat: protocol::wl_seat::WlSeat,
     ) {
-        // Ignored
+        self.seat_index(&seat);
     }
 
     fn new_capability(
@@ -915,11 +1419,42 @@ impl sctk::seat::SeatHandler for Bar {
         seat: protocol::wl_seat::WlSeat,
         capability: smithay_client_toolkit::seat::Capability,
     ) {
-        if capability == sctk::seat::Capability::Pointer && self.pointer.is_none() {
-            #[cfg(feature = "logs")]
-            log::info!("Initializing pointer");
-            let pointer = self.seat_state.get_pointer(qh, &seat).unwrap();
-            self.pointer = Some(pointer);
+        let idx = self.seat_index(&seat);
+        let input = &mut self.seats[idx];
+        match capability {
+            Capability::Pointer if input.pointer.is_none() => {
+                #[cfg(feature = "logs")]
+                log::info!("Initializing pointer");
+                let surface = self.compositor_state.create_surface(qh);
+                let pointer = self.seat_state.get_pointer_with_theme(
+                    qh,
+                    &seat,
+                    self.shm.wl_shm(),
+                    surface,
+                    ThemeSpec::default(),
+                );
+                match pointer {
+                    Ok(pointer) => input.pointer = Some(pointer),
+                    Err(e) => log::error!("Cannot get pointer: {}", e),
+                }
+            }
+            Capability::Touch if input.touch.is_none() => {
+                #[cfg(feature = "logs")]
+                log::info!("Initializing touch");
+                match self.seat_state.get_touch(qh, &seat) {
+                    Ok(touch) => input.touch = Some(touch),
+                    Err(e) => log::error!("Cannot get touch: {}", e),
+                }
+            }
+            Capability::Keyboard if input.keyboard.is_none() => {
+                #[cfg(feature = "logs")]
+                log::info!("Initializing keyboard");
+                match self.seat_state.get_keyboard(qh, &seat, None) {
+                    Ok(keyboard) => input.keyboard = Some(keyboard),
+                    Err(e) => log::error!("Cannot get keyboard: {}", e),
+                }
+            }
+            _ => {}
         }
     }
 
@@ -927,19 +1462,310 @@ impl sctk::seat::SeatHandler for Bar {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _seat: protocol::wl_seat::WlSeat,
-        _capability: smithay_client_toolkit::seat::Capability,
+        seat: protocol::wl_seat::WlSeat,
+        capability: smithay_client_toolkit::seat::Capability,
     ) {
-        todo!()
+        #[cfg(feature = "logs")]
+        log::info!("Releasing {}", capability);
+        let Some(idx) = self.seats.iter().position(|v| v.seat == seat) else {
+            return;
+        };
+        self.seats[idx].release(capability);
+        // Areas stop being hovered by a removed pointer
+        self.update_hover();
     }
 
     fn remove_seat(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _seat: protocol::wl_seat::WlSeat,
+        seat: protocol::wl_seat::WlSeat,
+    ) {
+        #[cfg(feature = "logs")]
+        log::info!("Removing seat");
+        self.seats.retain(|v| v.seat != seat);
+        self.update_hover();
+    }
+}
+
+impl TouchHandler for Bar {
+    fn down(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        touch: &WlTouch,
+        serial: u32,
+        _time: u32,
+        surface: WlSurface,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        let Some(input) = self.touch_seat(touch) else {
+            return;
+        };
+        input.touches.insert(
+            id,
+            TouchPoint {
+                surface,
+                start: position,
+                position,
+                since: Instant::now(),
+                serial,
+            },
+        );
+    }
+
+    fn up(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        touch: &WlTouch,
+        _serial: u32,
+        _time: u32,
+        id: i32,
+    ) {
+        let Some(input) = self.touch_seat(touch) else {
+            return;
+        };
+        let seat = input.seat.clone();
+        let Some(point) = input.touches.remove(&id) else {
+            return;
+        };
+        if let Some(menu) = self
+            .menu
+            .as_ref()
+            .filter(|v| point.surface == *v.popup.popup().wl_surface())
+        {
+            if let Some(row) = menu.popup.row_at(point.position.1) {
+                self.choose(row);
+            }
+            return;
+        }
+        if point.surface != *self.layer.wl_surface() {
+            return;
+        }
+        let (dx, dy) = (
+            point.position.0 - point.start.0,
+            point.position.1 - point.start.1,
+        );
+        // Swiping right scrolls up, like dragging content down
+        let button = if dx.abs() >= crate::consts::SWIPE_DISTANCE && dx.abs() > dy.abs() {
+            if dx > 0. {
+                4
+            } else {
+                5
+            }
+        } else if point.since.elapsed() >= crate::consts::LONG_PRESS {
+            3
+        } else {
+            1
+        };
+        let _matched = self.click(&seat, button, point.start.0, point.serial);
+        #[cfg(feature = "logs")]
+        match _matched {
+            Some(number) => log::info!("Touch as button {} triggering action #{}", button, number),
+            None => log::info!("Touch as button {} triggering nothing", button),
+        }
+    }
+
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        touch: &WlTouch,
+        _time: u32,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        if let Some(point) = self.touch_seat(touch).and_then(|v| v.touches.get_mut(&id)) {
+            point.position = position;
+        }
+    }
+
+    fn shape(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        _id: i32,
+        _major: f64,
+        _minor: f64,
+    ) {
+    }
+
+    fn orientation(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        _id: i32,
+        _orientation: f64,
+    ) {
+    }
+
+    fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, touch: &WlTouch) {
+        if let Some(input) = self.touch_seat(touch) {
+            input.touches.clear();
+        }
+    }
+}
+
+impl KeyboardHandler for Bar {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+        _raw: &[u32],
+        _keysyms: &[Keysym],
+    ) {
+    }
+
+    fn leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+    ) {
+        // Modifiers are only sent to the focused surface, so they would stay held otherwise
+        if let Some(input) = self
+            .seats
+            .iter_mut()
+            .find(|v| v.keyboard.as_ref() == Some(keyboard))
+        {
+            input.modifiers.leave();
+        }
+    }
+
+    fn press_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        event: KeyEvent,
+    ) {
+        let Some(menu) = &self.menu else {
+            return;
+        };
+        let last = menu.cmds.len() - 1;
+        let selected = menu.popup.selected();
+        match event.keysym {
+            Keysym::Up => self.select(Some(selected.map_or(last, |v| v.saturating_sub(1)))),
+            Keysym::Down => self.select(Some(selected.map_or(0, |v| (v + 1).min(last)))),
+            Keysym::Home => self.select(Some(0)),
+            Keysym::End => self.select(Some(last)),
+            Keysym::Return | Keysym::KP_Enter => {
+                if let Some(row) = selected {
+                    self.choose(row);
+                }
+            }
+            Keysym::Escape => self.close_menu(),
+            _ => {}
+        }
+    }
+
+    fn release_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _event: KeyEvent,
+    ) {
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        keyboard: &WlKeyboard,
+        _serial: u32,
+        modifiers: Modifiers,
+        _layout: u32,
     ) {
-        todo!()
+        if let Some(input) = self
+            .seats
+            .iter_mut()
+            .find(|v| v.keyboard.as_ref() == Some(keyboard))
+        {
+            input.modifiers.update(modifiers);
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for Bar {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            state.rescale(scale.max(1));
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for Bar {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // No events
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for Bar {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // No events
+    }
+}
+
+impl Dispatch<WpViewport, ()> for Bar {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // No events
+    }
+}
+
+impl Dispatch<WlCallback, ()> for Bar {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlCallback,
+        event: wl_callback::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Sent by `Waker`
+        if let wl_callback::Event::Done { .. } = event {
+            state.show_tooltip();
+            state.draw();
+        }
     }
 }
 
@@ -947,8 +1773,58 @@ sctk::delegate_registry!(Bar);
 sctk::delegate_compositor!(Bar);
 sctk::delegate_xdg_shell!(Bar);
 sctk::delegate_xdg_window!(Bar);
+sctk::delegate_xdg_popup!(Bar);
 sctk::delegate_output!(Bar);
 sctk::delegate_shm!(Bar);
 sctk::delegate_layer!(Bar);
 sctk::delegate_seat!(Bar);
 sctk::delegate_pointer!(Bar);
+sctk::delegate_keyboard!(Bar);
+sctk::delegate_touch!(Bar);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Color;
+
+    #[test]
+    fn timer_wakes_once_at_the_last_deadline() {
+        let (sender, receiver) = mpsc::channel();
+        let timer = Timer::new(move || sender.send(Instant::now()).unwrap());
+        let start = Instant::now();
+        timer.wake_at(start + Duration::from_millis(50));
+        timer.wake_at(start + Duration::from_millis(100));
+        let woken = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(woken >= start + Duration::from_millis(100));
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn plain_clicks_run_after_the_keyboard_leaves_with_shift_held() {
+        let fonts = [];
+        let ctx = crate::layout::Context {
+            fonts: &fonts,
+            icons: &IconCache::default(),
+            series: &HashMap::new(),
+            foreground_color: Color::new(255, 255, 255, 255),
+            background_color: Color::new(0, 0, 0, 255),
+            width: 100,
+            height: 20,
+            line_top: 2,
+            line_height: 16,
+            marquee_speed: 30.,
+            marquee_pause: 1.,
+            scale: 1.,
+        };
+        let layout = Layout::new(&ctx, "%{A:plain:}%{O10}%{A}").unwrap();
+        let mut modifiers = HeldModifiers::default();
+        modifiers.update(Modifiers {
+            shift: true,
+            ..Default::default()
+        });
+        assert!(layout.click(10, 1, modifiers.held()).is_none());
+        modifiers.leave();
+        let (_, action) = layout.click(10, 1, modifiers.held()).unwrap();
+        assert_eq!(action.cmd, "plain");
+    }
+}